@@ -2,19 +2,33 @@
 use super::RTsonResult;
 pub use rustson::deser::Reader;
 use rustson::spec::*;
-use rustr::{SEXP, IntoR, RawVec, RList, CharVec, R_NamesSymbol, ToSEXP, Rf_setAttrib, IntVec, NumVec};
-use ::{value_to_r, RTsonError};
+use rustr::*;
+use ::{set_class, tag_scalar, tag_map, na_real, f32_to_real, integer64_to_real, uint64_to_integer64, narrow_integers, list_elt, int_to_real_lossy, Losses, mk_char_utf8, mk_char_bytes, utf8_strings, RTsonError, NA_INTEGER};
+use options::{DecodeOptions, Utf8Policy};
+use rtype::{is_rtype, rtype_to_r};
+use json::decode_json_r;
 use rustson::VERSION;
+use std::cell::Cell;
+use std::io::Cursor;
 
 pub trait RDeserializer {
     fn read(&self, reader: &mut dyn Reader) -> RTsonResult<SEXP>;
 }
 
-pub struct RTsonDeserializer {}
+pub struct RTsonDeserializer {
+    options: DecodeOptions,
+    losses: Losses,
+    // Bytes the document may still claim, elements claimed so far and
+    // current nesting, checked against the limits.
+    budget: Cell<usize>,
+    elements: Cell<usize>,
+    depth: Cell<usize>,
+}
 pub struct RJsonDeserializer {}
 pub struct RBinaryDeserializer {}
 pub struct RUTF8Deserializer {}
 
+// Leaves the precision lost for take_warnings, as RTsonDeserializer::read.
 impl RDeserializer for RTsonDeserializer{
     fn read(&self, reader: &mut dyn Reader) -> RTsonResult<SEXP>{
         RTsonDeserializer::read(self, reader)
@@ -29,12 +43,7 @@ impl RDeserializer for RJsonDeserializer {
         if buf.is_empty() {
             Ok(().intor()?)
         } else {
-            match rustson::decode_json(&buf) {
-                Ok(object) => {
-                    Ok((value_to_r(&object)?).intor()?)
-                }
-                Err(e) => Err(RTsonError::new(e.to_string()))
-            }
+            decode_json_r(&buf, &DecodeOptions::default())
         }
     }
 }
@@ -61,19 +70,68 @@ impl RDeserializer for RUTF8Deserializer {
         let mut buf = Vec::new();
         reader.read_all(&mut buf)?;
 
-        unsafe {
-            let utf8str = String::from_utf8_unchecked(buf);
-            Ok(utf8str.intor()?)
+        match String::from_utf8(buf) {
+            Ok(utf8str) => Ok(utf8_strings(&[utf8str])?),
+            Err(e) => Err(bad_utf8(e.utf8_error())),
         }
     }
 }
 
 impl RTsonDeserializer {
     pub fn new() -> RTsonDeserializer {
-        RTsonDeserializer {}
+        RTsonDeserializer::with_options(DecodeOptions::default())
     }
 
+    pub fn with_options(options: DecodeOptions) -> RTsonDeserializer {
+        let losses = Losses::new(options.strict);
+        RTsonDeserializer {
+            options,
+            losses,
+            budget: Cell::new(0),
+            elements: Cell::new(0),
+            depth: Cell::new(0),
+        }
+    }
+
+    // The read functions do not warn about the precision lost, see
+    // take_warnings.
+    //
+    // The size of a reader is not known, so only `max_bytes` and
+    // `max_elements` bound what a document may claim, and nothing is
+    // allocated for a list before its elements are read. read_slice and
+    // read_cursor bound lengths by the input size instead.
     pub fn read(&self, reader: &mut dyn Reader) -> RTsonResult<SEXP> {
+        self.read_sized(&mut ReaderSource { reader }, None)
+    }
+
+    // Reads a whole document from the borrowed `bytes`, without copying
+    // them. Their size then bounds every length the document claims.
+    pub fn read_slice(&self, bytes: &[u8]) -> RTsonResult<SEXP> {
+        self.read_sized(&mut SliceSource { bytes, pos: 0 }, Some(bytes.len()))
+    }
+
+    // Same as read_slice for the bytes left in `cursor`, which is moved
+    // past the document.
+    pub fn read_cursor(&self, cursor: &mut Cursor<&[u8]>) -> RTsonResult<SEXP> {
+        let start = std::cmp::min(cursor.position(), cursor.get_ref().len() as u64) as usize;
+        let bytes = &cursor.get_ref()[start..];
+        let mut source = SliceSource { bytes, pos: 0 };
+        let object = self.read_sized(&mut source, Some(bytes.len()))?;
+        cursor.set_position((start + source.pos) as u64);
+        Ok(object)
+    }
+
+    fn read_sized(&self, reader: &mut dyn Source, size: Option<usize>) -> RTsonResult<SEXP> {
+        let max_bytes = self.options.limits.max_bytes.unwrap_or(std::usize::MAX);
+        if let Some(size) = size {
+            if size > max_bytes {
+                return Err(limit_error(format!("document of {} bytes exceeds {} bytes", size, max_bytes)));
+            }
+        }
+        self.budget.set(std::cmp::min(max_bytes, size.unwrap_or(std::usize::MAX)));
+        self.elements.set(0);
+        self.depth.set(0);
+
         let itype = self.read_type(reader)?;
 
         if itype != STRING_TYPE {
@@ -86,197 +144,374 @@ impl RTsonDeserializer {
             return Err(RTsonError::new("wrong version"));
         }
 
+        self.losses.clear();
         self.read_object(reader)
     }
 
-    fn read_type(&self, reader: &mut dyn Reader) -> RTsonResult<u8> {
+    // The messages of the precision lost by the last read, as an R
+    // character vector or NULL, for the caller to warn about once it holds
+    // no Rust value that needs dropping. `object` is kept protected
+    // meanwhile.
+    pub fn take_warnings(&self, object: SEXP) -> RTsonResult<SEXP> {
+        self.losses.take(object)
+    }
+
+    fn read_type(&self, reader: &mut dyn Source) -> RTsonResult<u8> {
         let t = reader.read_u8()?;
 
         Ok(t)
     }
 
-    fn read_len(&self, reader: &mut dyn Reader) -> RTsonResult<usize> {
-        let len = reader.read_u32()? as usize;
+    // Whether `v` is the NA sentinel `na` of its integer list type.
+    fn is_na<T: PartialEq>(&self, v: T, na: T) -> bool {
+        self.options.na_sentinels && v == na
+    }
 
+    // Reads the length of a list whose elements take at least `elem_size`
+    // bytes each, failing if the limits cannot hold it.
+    fn read_len(&self, reader: &mut dyn Source, elem_size: usize) -> RTsonResult<usize> {
+        let len = reader.read_u32()? as usize;
+        self.claim_bytes(len.saturating_mul(elem_size))?;
+        self.claim_elements(len)?;
         Ok(len)
     }
 
-    fn read_string(&self, reader: &mut dyn Reader) -> RTsonResult<String> {
+    fn claim_bytes(&self, bytes: usize) -> RTsonResult<()> {
+        if bytes > self.budget.get() {
+            return Err(limit_error(format!("length of {} bytes exceeds the document size", bytes)));
+        }
+        self.budget.set(self.budget.get() - bytes);
+        Ok(())
+    }
+
+    fn claim_elements(&self, len: usize) -> RTsonResult<()> {
+        let elements = self.elements.get().saturating_add(len);
+        if let Some(max) = self.options.limits.max_elements {
+            if elements > max {
+                return Err(limit_error(format!("more than {} elements", max)));
+            }
+        }
+        self.elements.set(elements);
+        Ok(())
+    }
+
+    fn enter(&self) -> RTsonResult<()> {
+        let depth = self.depth.get() + 1;
+        if let Some(max) = self.options.limits.max_depth {
+            if depth > max {
+                return Err(limit_error(format!("nesting deeper than {}", max)));
+            }
+        }
+        self.depth.set(depth);
+        Ok(())
+    }
+
+    fn leave(&self) {
+        self.depth.set(self.depth.get() - 1);
+    }
+
+    fn read_bytes(&self, reader: &mut dyn Source) -> RTsonResult<Vec<u8>> {
         let mut done = false;
         let mut vec = Vec::new();
+        let max = self.options.limits.max_string_length.unwrap_or(std::usize::MAX);
         while !done {
             let byte = reader.read_u8()?;
             if byte == 0 {
                 done = true;
+            } else if vec.len() == max {
+                return Err(limit_error(format!("string longer than {} bytes", max)));
             } else {
                 vec.push(byte);
             }
         }
+        Ok(vec)
+    }
+
+    // Reads a string needed as text, such as a map key, invalid UTF-8 is
+    // replaced unless the policy is strict.
+    fn read_string(&self, reader: &mut dyn Source) -> RTsonResult<String> {
+        match String::from_utf8(self.read_bytes(reader)?) {
+            Ok(value) => Ok(value),
+            Err(e) => match self.options.invalid_utf8 {
+                Utf8Policy::Strict => Err(bad_utf8(e.utf8_error())),
+                _ => Ok(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+            },
+        }
+    }
 
-        if let Ok(value) = String::from_utf8(vec) {
-            Ok(value)
+    // A CHARSXP for `bytes` according to the invalid UTF-8 policy.
+    fn mk_char(&self, bytes: &[u8]) -> RTsonResult<SEXP> {
+        match std::str::from_utf8(bytes) {
+            Ok(value) => Ok(mk_char_utf8(value)),
+            Err(e) => match self.options.invalid_utf8 {
+                Utf8Policy::Strict => Err(bad_utf8(e)),
+                Utf8Policy::Lossy => Ok(mk_char_utf8(&String::from_utf8_lossy(bytes))),
+                Utf8Policy::Bytes => Ok(mk_char_bytes(bytes)),
+            },
+        }
+    }
+
+    fn mk_strings(&self, values: &[Vec<u8>]) -> RTsonResult<SEXP> {
+        let object = CharVec::alloc(values.len());
+        for (i, value) in values.iter().enumerate() {
+            let value = self.mk_char(value)?;
+            unsafe {
+                SET_STRING_ELT(object.s(), i as R_xlen_t, value);
+            }
+        }
+        Ok(object.intor()?)
+    }
+
+    // Elements allocated up front for a list claiming `len`. A Reader does
+    // not bound lengths, so its lists only grow with what is actually read.
+    fn capacity(&self, reader: &dyn Source, len: usize) -> usize {
+        if reader.bounded() {
+            len
         } else {
-            Err(RTsonError::new("bad string"))
+            std::cmp::min(len, UNBOUNDED_CAPACITY)
+        }
+    }
+
+    // Reads the `len` values of a list with `read`.
+    fn read_values<T, F>(&self, reader: &mut dyn Source, len: usize, read: F) -> RTsonResult<Vec<T>>
+        where F: Fn(&mut dyn Source) -> RTsonResult<T> {
+        let mut values = Vec::with_capacity(self.capacity(reader, len));
+        for _ in 0..len {
+            values.push(read(&mut *reader)?);
         }
+        Ok(values)
     }
 
-    fn read_object(&self, reader: &mut dyn Reader) -> RTsonResult<SEXP> {
+    // Reads the `len` objects of a list or map with `read`, into a list
+    // reallocated as it fills up when `len` is not bounded.
+    fn read_list<F>(&self, reader: &mut dyn Source, len: usize, mut read: F) -> RTsonResult<RList>
+        where F: FnMut(&mut dyn Source) -> RTsonResult<SEXP> {
+        let mut lst = RList::alloc(self.capacity(reader, len));
+        for i in 0..len {
+            if i == lst.rsize() as usize {
+                lst = resize_list(&lst, std::cmp::min(len, 2 * i))?;
+            }
+            let obj = read(&mut *reader)?;
+            lst.set(i, obj)?;
+        }
+        Ok(lst)
+    }
+
+    fn read_object(&self, reader: &mut dyn Source) -> RTsonResult<SEXP> {
+        self.enter()?;
+        let object = self.read_value(reader);
+        self.leave();
+        object
+    }
+
+    fn read_value(&self, reader: &mut dyn Source) -> RTsonResult<SEXP> {
         let itype = self.read_type(reader)?;
         match itype {
             NULL_TYPE => Ok(().intor()?),
-            STRING_TYPE => Ok(self.read_string(reader)?.intor()?),
-            INTEGER_TYPE => Ok(reader.read_i32()?.intor()?),
-            DOUBLE_TYPE => Ok(reader.read_f64()?.intor()?),
+            STRING_TYPE => Ok(tag_scalar(self.mk_strings(&[self.read_bytes(reader)?])?, &self.options)?),
+            INTEGER_TYPE => Ok(tag_scalar(reader.read_i32()?.intor()?, &self.options)?),
+            DOUBLE_TYPE => Ok(tag_scalar(reader.read_f64()?.intor()?, &self.options)?),
             BOOL_TYPE => {
                 Ok((reader.read_u8()? > 0).intor()?)
             }
             LIST_TYPE => {
-                let len = self.read_len(reader)?;
-                let mut lst = RList::alloc(len);
-
-                for i in 0..len {
-                    let obj = self.read_object(reader)?;
-                    lst.set(i, obj)?;
-                }
-
+                let len = self.read_len(reader, 1)?;
+                let lst = self.read_list(reader, len, |reader| self.read_object(reader))?;
                 Ok(lst.intor()?)
             }
             MAP_TYPE => {
-                let len = self.read_len(reader)?;
-
-                let mut names = CharVec::alloc(len);
-                let mut values = RList::alloc(len);
-
-                for i in 0..len {
-                    let _itype = self.read_type(reader)?;
-                    if _itype != STRING_TYPE {
+                let len = self.read_len(reader, 3)?;
+                let mut keys = Vec::with_capacity(self.capacity(reader, len));
+                let values = self.read_list(reader, len, |reader| {
+                    if self.read_type(reader)? != STRING_TYPE {
                         return Err(RTsonError::new("wrong format"));
                     }
-                    let name = self.read_string(reader)?;
-
-                    names.set(i, &name as &str)?;
-                    values.set(i, self.read_object(reader)?)?;
+                    keys.push(self.read_bytes(reader)?);
+                    self.read_object(reader)
+                })?;
+                let values = if self.options.sort_keys { sort_map(&mut keys, &values)? } else { values };
+
+                let names: Vec<String> = keys.iter()
+                    .map(|x| String::from_utf8_lossy(x).into_owned())
+                    .collect();
+                if is_rtype(&names) {
+                    return rtype_to_r(&names, &values, false);
                 }
 
                 unsafe {
-                    Rf_setAttrib(values.s(), R_NamesSymbol, names.s());
-                    Ok(values.s())
+                    Rf_setAttrib(values.s(), R_NamesSymbol, self.mk_strings(&keys)?);
                 }
+                Ok(tag_map(values.s(), &self.options)?)
             }
             LIST_UINT8_TYPE => {
-                let len = self.read_len(reader)?;
-                let mut values = RawVec::alloc(len);
-                unsafe {
-                    for i in 0..len {
-                        values.uset(i, reader.read_u8()?);
+                let len = self.read_len(reader, 1)?;
+                match reader.borrow_bytes(len) {
+                    Some(bytes) => {
+                        let bytes = bytes?;
+                        let values = RawVec::alloc(len);
+                        if len > 0 {
+                            unsafe {
+                                std::slice::from_raw_parts_mut(RAW(values.s()), len).copy_from_slice(bytes);
+                            }
+                        }
+                        Ok(values.intor()?)
                     }
+                    None => Ok(self.read_values(reader, len, |r| r.read_u8())?.intor()?),
                 }
-
-                Ok(values.intor()?)
             }
             LIST_INT8_TYPE => {
-                let len = self.read_len(reader)?;
-
-                let mut values = IntVec::alloc(len);
-                unsafe {
-                    for i in 0..len {
-                        values.uset(i, reader.read_i8()? as i32);
-                    }
-                }
-
-                Ok(values.intor()?)
+                let len = self.read_len(reader, 1)?;
+                let values = self.read_values(reader, len, |r| {
+                    let v = r.read_i8()?;
+                    Ok(if self.is_na(v, std::i8::MIN) { NA_INTEGER } else { v as i32 })
+                })?;
+                Ok(set_class(values.intor()?, "int8")?)
             }
             LIST_UINT16_TYPE => {
-                let len = self.read_len(reader)?;
-
-                let mut values = IntVec::alloc(len);
-                unsafe {
-                    for i in 0..len {
-                        values.uset(i, reader.read_u16()? as i32);
-                    }
-                }
-
-                Ok(values.intor()?)
+                let len = self.read_len(reader, 2)?;
+                let values = self.read_values(reader, len, |r| {
+                    let v = r.read_u16()?;
+                    Ok(if self.is_na(v, std::u16::MAX) { NA_INTEGER } else { v as i32 })
+                })?;
+                Ok(set_class(values.intor()?, "uint16")?)
             }
             LIST_INT16_TYPE => {
-                let len = self.read_len(reader)?;
-                let mut values = IntVec::alloc(len);
-                unsafe {
-                    for i in 0..len {
-                        values.uset(i, reader.read_i16()? as i32);
-                    }
-                }
-                Ok(values.intor()?)
+                let len = self.read_len(reader, 2)?;
+                let values = self.read_values(reader, len, |r| {
+                    let v = r.read_i16()?;
+                    Ok(if self.is_na(v, std::i16::MIN) { NA_INTEGER } else { v as i32 })
+                })?;
+                Ok(set_class(values.intor()?, "int16")?)
             }
 
+            LIST_UINT32_TYPE if self.options.narrow_integers => {
+                let len = self.read_len(reader, 4)?;
+                let values = self.read_values(reader, len, |r| {
+                    let v = r.read_u32()?;
+                    Ok(if self.is_na(v, std::u32::MAX) { None } else { Some(v as i128) })
+                })?;
+                Ok(narrow_integers(&values, "uint32")?)
+            }
             LIST_UINT32_TYPE => {
-                let len = self.read_len(reader)?;
-                let mut values = NumVec::alloc(len);
-                unsafe {
-                    for i in 0..len {
-                        values.uset(i, reader.read_u32()? as f64);
-                    }
-                }
-                Ok(values.intor()?)
+                let len = self.read_len(reader, 4)?;
+                let values = self.read_values(reader, len, |r| {
+                    let v = r.read_u32()?;
+                    Ok(if self.is_na(v, std::u32::MAX) { na_real() } else { v as f64 })
+                })?;
+                Ok(set_class(values.intor()?, "uint32")?)
             }
             LIST_INT32_TYPE => {
-                let len = self.read_len(reader)?;
-                let mut values = IntVec::alloc(len);
-                unsafe {
-                    for i in 0..len {
-                        values.uset(i, reader.read_i32()?);
+                let len = self.read_len(reader, 4)?;
+                match reader.borrow_bytes(len * 4) {
+                    Some(bytes) => {
+                        let bytes = bytes?;
+                        let values = IntVec::alloc(len);
+                        if len > 0 {
+                            unsafe {
+                                let dst = INTEGER(values.s());
+                                std::ptr::copy_nonoverlapping(bytes.as_ptr(), dst as *mut u8, len * 4);
+                                if cfg!(target_endian = "big") {
+                                    for x in std::slice::from_raw_parts_mut(dst, len) {
+                                        *x = i32::from_le(*x);
+                                    }
+                                }
+                            }
+                        }
+                        Ok(values.intor()?)
                     }
+                    None => Ok(self.read_values(reader, len, |r| r.read_i32())?.intor()?),
                 }
-                Ok(values.intor()?)
+            }
+            LIST_INT64_TYPE if self.options.narrow_integers => {
+                let len = self.read_len(reader, 8)?;
+                let values = self.read_values(reader, len, |r| {
+                    let v = r.read_i64()?;
+                    Ok(if self.is_na(v, std::i64::MIN) { None } else { Some(v as i128) })
+                })?;
+                Ok(narrow_integers(&values, "int64")?)
+            }
+            LIST_UINT64_TYPE if self.options.narrow_integers => {
+                let len = self.read_len(reader, 8)?;
+                let values = self.read_values(reader, len, |r| {
+                    let v = r.read_u64()?;
+                    Ok(if self.is_na(v, std::u64::MAX) { None } else { Some(v as i128) })
+                })?;
+                Ok(narrow_integers(&values, "uint64")?)
+            }
+            LIST_INT64_TYPE if self.options.integer64 => {
+                let len = self.read_len(reader, 8)?;
+                let values = self.read_values(reader, len, |r| Ok(integer64_to_real(r.read_i64()?)))?;
+                Ok(set_class(values.intor()?, "integer64")?)
+            }
+            LIST_UINT64_TYPE if self.options.integer64 => {
+                let len = self.read_len(reader, 8)?;
+                let ints = self.read_values(reader, len, |r| r.read_u64())?;
+                let values = ints.into_iter().enumerate()
+                    .map(|(i, x)| Ok(integer64_to_real(uint64_to_integer64(x, i, self.options.na_sentinels)?)))
+                    .collect::<RTsonResult<Vec<f64>>>()?;
+                Ok(set_class(values.intor()?, "integer64")?)
             }
             LIST_INT64_TYPE => {
-                let len = self.read_len(reader)?;
-                let mut values = NumVec::alloc(len);
-                unsafe {
-                    for i in 0..len {
-                        values.uset(i, reader.read_i64()? as f64);
-                    }
-                }
-                Ok(values.intor()?)
+                let len = self.read_len(reader, 8)?;
+                let ints = self.read_values(reader, len, |r| r.read_i64())?;
+                self.losses.check("int64", "double", ints.iter()
+                    .map(|x| !self.is_na(*x, std::i64::MIN) && int_to_real_lossy(*x as i128)))?;
+
+                let values: Vec<f64> = ints.into_iter()
+                    .map(|v| if self.is_na(v, std::i64::MIN) { na_real() } else { v as f64 })
+                    .collect();
+                Ok(set_class(values.intor()?, "int64")?)
             }
             LIST_UINT64_TYPE => {
-                let len = self.read_len(reader)?;
-                let mut values = NumVec::alloc(len);
-                unsafe {
-                    for i in 0..len {
-                        values.uset(i, reader.read_u64()? as f64);
-                    }
-                }
-                Ok(values.intor()?)
+                let len = self.read_len(reader, 8)?;
+                let ints = self.read_values(reader, len, |r| r.read_u64())?;
+                self.losses.check("uint64", "double", ints.iter()
+                    .map(|x| !self.is_na(*x, std::u64::MAX) && int_to_real_lossy(*x as i128)))?;
+
+                let values: Vec<f64> = ints.into_iter()
+                    .map(|v| if self.is_na(v, std::u64::MAX) { na_real() } else { v as f64 })
+                    .collect();
+                Ok(set_class(values.intor()?, "uint64")?)
             }
             LIST_FLOAT32_TYPE => {
-                let len = self.read_len(reader)?;
-                let mut values = NumVec::alloc(len);
-                unsafe {
-                    for i in 0..len {
-                        values.uset(i, reader.read_f32()? as f64);
-                    }
-                }
-                Ok(values.intor()?)
+                let len = self.read_len(reader, 4)?;
+                let values = self.read_values(reader, len, |r| Ok(f32_to_real(r.read_f32()?)))?;
+                Ok(set_class(values.intor()?, "float32")?)
             }
             LIST_FLOAT64_TYPE => {
-                let len = self.read_len(reader)?;
-                let mut values = NumVec::alloc(len);
-                unsafe {
-                    for i in 0..len {
-                        values.uset(i, reader.read_f64()?);
+                let len = self.read_len(reader, 8)?;
+                match reader.borrow_bytes(len * 8) {
+                    Some(bytes) => {
+                        let bytes = bytes?;
+                        let values = NumVec::alloc(len);
+                        if len > 0 {
+                            unsafe {
+                                let dst = REAL(values.s());
+                                std::ptr::copy_nonoverlapping(bytes.as_ptr(), dst as *mut u8, len * 8);
+                                if cfg!(target_endian = "big") {
+                                    for x in std::slice::from_raw_parts_mut(dst, len) {
+                                        *x = f64::from_bits(u64::from_le(x.to_bits()));
+                                    }
+                                }
+                            }
+                        }
+                        Ok(values.intor()?)
                     }
+                    None => Ok(self.read_values(reader, len, |r| r.read_f64())?.intor()?),
                 }
-
-                Ok(values.intor()?)
             }
             LIST_STRING_TYPE => {
-                let mut len_in_bytes = self.read_len(reader)?;
+                let mut len_in_bytes = reader.read_u32()? as usize;
+                self.claim_bytes(len_in_bytes)?;
 
                 let mut vec = Vec::new();
                 while len_in_bytes > 0 {
-                    let v = self.read_string(reader)?;
-                    len_in_bytes -= v.as_bytes().len() + 1;
+                    let v = self.read_bytes(reader)?;
+                    if v.len() + 1 > len_in_bytes {
+                        return Err(RTsonError::new("wrong format"));
+                    }
+                    len_in_bytes -= v.len() + 1;
+                    self.claim_elements(1)?;
                     vec.push(v);
                 }
 
@@ -284,7 +519,7 @@ impl RTsonDeserializer {
                     return Err(RTsonError::new("wrong format"));
                 }
 
-                Ok(vec.intor()?)
+                Ok(self.mk_strings(&vec)?)
             }
 
             _ => Err(RTsonError::new("wrong format")),
@@ -292,23 +527,411 @@ impl RTsonDeserializer {
     }
 }
 
-//struct RawVecReader {
-//    data: RawVec,
-//    _remaining: usize,
-//}
-//
-//impl RawVecReader {
-//    pub fn new(data: RawVec) -> RawVecReader {
-//        let len = data.rsize() as usize;
-//        RawVecReader {data, _remaining: len}
-//    }
-//
-//    fn remaining(&self) -> usize {
-//        self._remaining
-//    }
-//}
-//
-//impl Reader for RawVecReader {
-//
-//}
+// Input of RTsonDeserializer, read in little endian.
+trait Source {
+    fn read_u8(&mut self) -> RTsonResult<u8>;
+    fn read_i8(&mut self) -> RTsonResult<i8>;
+    fn read_u16(&mut self) -> RTsonResult<u16>;
+    fn read_i16(&mut self) -> RTsonResult<i16>;
+    fn read_u32(&mut self) -> RTsonResult<u32>;
+    fn read_i32(&mut self) -> RTsonResult<i32>;
+    fn read_u64(&mut self) -> RTsonResult<u64>;
+    fn read_i64(&mut self) -> RTsonResult<i64>;
+    fn read_f32(&mut self) -> RTsonResult<f32>;
+    fn read_f64(&mut self) -> RTsonResult<f64>;
+    // Whether the input size bounds every length read, so that lists may
+    // be allocated before their elements are read.
+    fn bounded(&self) -> bool;
+    // The next `len` bytes borrowed from the input, so that numeric lists
+    // are copied into R memory in one go. None when the input cannot lend
+    // them, lists are then read element by element.
+    fn borrow_bytes(&mut self, len: usize) -> Option<RTsonResult<&[u8]>>;
+}
+
+struct ReaderSource<'a> {
+    reader: &'a mut dyn Reader,
+}
+
+impl<'a> Source for ReaderSource<'a> {
+    fn read_u8(&mut self) -> RTsonResult<u8> { Ok(self.reader.read_u8()?) }
+    fn read_i8(&mut self) -> RTsonResult<i8> { Ok(self.reader.read_i8()?) }
+    fn read_u16(&mut self) -> RTsonResult<u16> { Ok(self.reader.read_u16()?) }
+    fn read_i16(&mut self) -> RTsonResult<i16> { Ok(self.reader.read_i16()?) }
+    fn read_u32(&mut self) -> RTsonResult<u32> { Ok(self.reader.read_u32()?) }
+    fn read_i32(&mut self) -> RTsonResult<i32> { Ok(self.reader.read_i32()?) }
+    fn read_u64(&mut self) -> RTsonResult<u64> { Ok(self.reader.read_u64()?) }
+    fn read_i64(&mut self) -> RTsonResult<i64> { Ok(self.reader.read_i64()?) }
+    fn read_f32(&mut self) -> RTsonResult<f32> { Ok(self.reader.read_f32()?) }
+    fn read_f64(&mut self) -> RTsonResult<f64> { Ok(self.reader.read_f64()?) }
+    fn bounded(&self) -> bool { false }
+    fn borrow_bytes(&mut self, _len: usize) -> Option<RTsonResult<&[u8]>> { None }
+}
+
+struct SliceSource<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SliceSource<'a> {
+    fn take(&mut self, len: usize) -> RTsonResult<&'a [u8]> {
+        if self.bytes.len() - self.pos < len {
+            return Err(RTsonError::new("unexpected end"));
+        }
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn take_array<T: Default + AsMut<[u8]>>(&mut self) -> RTsonResult<T> {
+        let mut value = T::default();
+        let len = value.as_mut().len();
+        value.as_mut().copy_from_slice(self.take(len)?);
+        Ok(value)
+    }
+}
+
+impl<'a> Source for SliceSource<'a> {
+    fn read_u8(&mut self) -> RTsonResult<u8> { Ok(self.take(1)?[0]) }
+    fn read_i8(&mut self) -> RTsonResult<i8> { Ok(self.take(1)?[0] as i8) }
+    fn read_u16(&mut self) -> RTsonResult<u16> { Ok(u16::from_le_bytes(self.take_array()?)) }
+    fn read_i16(&mut self) -> RTsonResult<i16> { Ok(i16::from_le_bytes(self.take_array()?)) }
+    fn read_u32(&mut self) -> RTsonResult<u32> { Ok(u32::from_le_bytes(self.take_array()?)) }
+    fn read_i32(&mut self) -> RTsonResult<i32> { Ok(i32::from_le_bytes(self.take_array()?)) }
+    fn read_u64(&mut self) -> RTsonResult<u64> { Ok(u64::from_le_bytes(self.take_array()?)) }
+    fn read_i64(&mut self) -> RTsonResult<i64> { Ok(i64::from_le_bytes(self.take_array()?)) }
+    fn read_f32(&mut self) -> RTsonResult<f32> { Ok(f32::from_bits(self.read_u32()?)) }
+    fn read_f64(&mut self) -> RTsonResult<f64> { Ok(f64::from_bits(self.read_u64()?)) }
+
+    fn bounded(&self) -> bool { true }
+    fn borrow_bytes(&mut self, len: usize) -> Option<RTsonResult<&[u8]>> { Some(self.take(len)) }
+}
+
+// Lists read from a Reader start with room for this many elements.
+const UNBOUNDED_CAPACITY: usize = 4096;
+
+// A copy of `lst` resized to `len` elements.
+fn resize_list(lst: &RList, len: usize) -> RTsonResult<RList> {
+    let mut resized = RList::alloc(len);
+    for i in 0..std::cmp::min(len, lst.rsize() as usize) {
+        resized.set(i, list_elt(lst.s(), i))?;
+    }
+    Ok(resized)
+}
+
+// The elements of `lst` in the order of their `keys`, which are sorted
+// along. Elements with the same key keep their order.
+fn sort_map(keys: &mut Vec<Vec<u8>>, lst: &RList) -> RTsonResult<RList> {
+    let mut order: Vec<usize> = (0..keys.len()).collect();
+    order.sort_by(|a, b| keys[*a].cmp(&keys[*b]));
+    let mut sorted = RList::alloc(order.len());
+    for (i, j) in order.iter().enumerate() {
+        sorted.set(i, list_elt(lst.s(), *j))?;
+    }
+    let sorted_keys = order.iter().map(|j| keys[*j].clone()).collect();
+    *keys = sorted_keys;
+    Ok(sorted)
+}
+
+fn limit_error(msg: String) -> RTsonError {
+    RTsonError::new(format!("limit : {}", msg))
+}
+
+fn bad_utf8(e: std::str::Utf8Error) -> RTsonError {
+    RTsonError::new(format!("bad string : invalid UTF-8 at byte {}", e.valid_up_to()))
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutil::*;
+    use ::{to_tson_with, from_tson_with, EncodeOptions, NaPolicy};
+    use rustson::{encode, Value};
+
+    #[test]
+    fn na_sentinels() {
+        let _r = session();
+        let x = r("structure(c(-128L, 1L), class = 'int8')");
+        let mut encode = EncodeOptions::default();
+        assert!(to_tson_with(x, &encode).is_err());
+
+        encode.na = NaPolicy::Error;
+        let bytes = to_tson_with(x, &encode).unwrap();
+        let y = from_tson_with(RawVec::rnew(bytes.s()).unwrap(), &DecodeOptions::default()).unwrap();
+        assert!(identical(y, r("structure(c(NA, 1L), class = 'int8')")));
+
+        let mut decode = DecodeOptions::default();
+        decode.na_sentinels = false;
+        let y = from_tson_with(bytes, &decode).unwrap();
+        assert!(identical(x, y));
+    }
+
+    // A document made of the version string followed by `body`.
+    fn document(body: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![STRING_TYPE];
+        bytes.extend_from_slice(VERSION.as_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    #[test]
+    fn reader_length_bounded_by_input() {
+        let _r = session();
+        let mut body = vec![LIST_FLOAT64_TYPE];
+        body.extend_from_slice(&std::u32::MAX.to_le_bytes());
+        let bytes = document(&body);
+        let mut cursor = Cursor::new(&bytes[..]);
+        let e = ::from_tson_reader(&mut cursor).unwrap_err();
+        assert!(e.to_string().contains("limit : "), "{}", e);
+    }
+
+    #[test]
+    fn reader_claims_not_allocated() {
+        let _r = session();
+        let claim = std::u32::MAX.to_le_bytes();
+        let mut narrow = DecodeOptions::default();
+        narrow.narrow_integers = true;
+        let mut integer64 = DecodeOptions::default();
+        integer64.integer64 = true;
+        for options in &[DecodeOptions::default(), narrow, integer64] {
+            let deser = RTsonDeserializer::with_options(options.clone());
+            for itype in &[LIST_INT64_TYPE, LIST_UINT64_TYPE, LIST_UINT32_TYPE, LIST_FLOAT64_TYPE,
+                           LIST_INT32_TYPE, LIST_UINT8_TYPE, LIST_INT8_TYPE, LIST_TYPE, MAP_TYPE] {
+                let mut body = vec![*itype];
+                body.extend_from_slice(&claim);
+                body.extend_from_slice(&[0; 16]);
+                let bytes = document(&body);
+                assert!(deser.read(&mut Cursor::new(&bytes[..])).is_err(), "{}", itype);
+            }
+        }
+    }
+
+    #[test]
+    fn reader_lists_grow() {
+        let _r = session();
+        let deser = RTsonDeserializer::new();
+        for code in &["as.list(1:10000)", "setNames(as.list(1:5000), paste0('k', 1:5000))", "1:10000"] {
+            let bytes = tson(code);
+            let x = deser.read(&mut Cursor::new(&bytes[..])).unwrap();
+            assert!(identical(x, deser.read_slice(&bytes).unwrap()), "{}", code);
+        }
+    }
+
+    #[test]
+    fn reader_moves_past_document() {
+        let _r = session();
+        let mut bytes = document(&[INTEGER_TYPE, 7, 0, 0, 0]);
+        bytes.push(42);
+        let mut cursor = Cursor::new(&bytes[..]);
+        let x = ::from_tson_reader(&mut cursor).unwrap();
+        assert!(identical(x, r("structure(7L, class = 'scalar')")));
+        assert_eq!(cursor.position() as usize, bytes.len() - 1);
+    }
+
+    fn tson(code: &str) -> Vec<u8> {
+        let raw = ::to_tson(r(code)).unwrap();
+        ::raw_bytes(&raw).to_vec()
+    }
+
+    // The payload of a document holding a single list of `len` values of
+    // `size` bytes, decoded one value at a time.
+    fn payload(bytes: &[u8], len: usize, size: usize) -> Vec<&[u8]> {
+        bytes[bytes.len() - len * size..].chunks(size).collect()
+    }
+
+    fn round_trip(code: &str) {
+        let _r = session();
+        let x = r(code);
+        let y = preserve(::from_tson(::to_tson(x).unwrap()).unwrap());
+        assert!(identical(x, y), "{} does not round trip through TSON", code);
+    }
+
+    #[test]
+    fn logical_round_trip() {
+        round_trip("c(TRUE, NA, FALSE)");
+        round_trip("c(NA, NA)");
+        round_trip("NA");
+        round_trip("logical(0)");
+        round_trip("c(FALSE, FALSE)");
+    }
+
+    #[test]
+    fn data_frame_round_trip() {
+        round_trip("data.frame(x = 1:2, y = c('a', NA), z = c(TRUE, NA), stringsAsFactors = FALSE)");
+        round_trip("data.frame(x = c(1.5, 2), f = factor(c('b', 'a')), row.names = c('a', 'b'))");
+        round_trip("data.frame(x = 1, row.names = 'a')");
+        round_trip("data.frame(x = integer(0), y = character(0), z = logical(0), w = numeric(0))");
+        round_trip("data.frame(f = factor(character(0)), d = as.Date(character(0)))");
+    }
+
+    #[test]
+    fn factor_round_trip() {
+        round_trip("factor(c('b', 'a', 'b'), levels = c('b', 'a'))");
+        round_trip("factor(c('b', NA, 'a'))");
+        round_trip("factor(c('lo', 'hi'), levels = c('lo', 'hi'), ordered = TRUE)");
+        round_trip("factor('a')");
+        round_trip("factor(character(0))");
+    }
+
+    #[test]
+    fn time_round_trip() {
+        round_trip("as.Date(c('2020-01-31', NA))");
+        round_trip("as.POSIXct(c(0, 1.5), origin = '1970-01-01', tz = 'UTC')");
+        round_trip("as.difftime(c(1, 2.5), units = 'hours')");
+        round_trip("as.Date(character(0))");
+    }
+
+    #[test]
+    fn array_round_trip() {
+        round_trip("matrix(1:6, 2)");
+        round_trip("matrix(1:4, 2, dimnames = list(c('a', 'b'), c('x', 'y')))");
+        round_trip("matrix(c(1.5, NA), 1, dimnames = list('a', NULL))");
+        round_trip("matrix(c('a', NA, 'c', 'd'), 2)");
+        round_trip("matrix(c(TRUE, NA, FALSE, TRUE), 2)");
+        round_trip("array(1:8, c(2, 2, 2), dimnames = list(r = c('a', 'b'), c = c('x', 'y'), NULL))");
+    }
+
+    #[test]
+    fn complex_round_trip() {
+        round_trip("complex(real = c(1, -2.5), imaginary = c(0, 3))");
+        round_trip("complex(real = NA_real_, imaginary = 1)");
+        round_trip("c(1i, NA)");
+        round_trip("complex(0)");
+    }
+
+    #[test]
+    fn scalar_round_trip() {
+        round_trip("structure('a', class = 'scalar')");
+        round_trip("structure(2L, class = 'scalar')");
+        round_trip("structure(NA_integer_, class = 'scalar')");
+        round_trip("structure(1.5, class = 'scalar')");
+        round_trip("structure(list(id = structure('x', class = 'scalar'), n = 1:2), class = 'tsonmap')");
+
+        let _r = session();
+        let options = DecodeOptions { tag_scalars: false, ..DecodeOptions::default() };
+        let x = from_tson_with(::to_tson(r("structure('a', class = 'scalar')")).unwrap(), &options).unwrap();
+        assert!(identical(preserve(x), r("'a'")));
+    }
+
+    // Maps, empty ones included, decode to tsonmap lists that are written
+    // back as maps.
+    #[test]
+    fn map_round_trip() {
+        let _r = session();
+        let deser = RTsonDeserializer::new();
+        for code in &["structure(list(), class = 'tsonmap')",
+                      "list(a = structure(list(), class = 'tsonmap'), b = list())",
+                      "list(a = 1, b = list(c = 'x'))"] {
+            let bytes = tson(code);
+            let x = preserve(deser.read_slice(&bytes).unwrap());
+            assert!(::inherits(x, "tsonmap").unwrap(), "{}", code);
+            assert_eq!(::raw_bytes(&::to_tson(x).unwrap()), &bytes[..], "{}", code);
+        }
+        let empty = preserve(deser.read_slice(&tson("structure(list(), class = 'tsonmap')")).unwrap());
+        assert_eq!(unsafe { Rf_xlength(empty) }, 0);
+    }
+
+    // Each typed list, its NA sentinel included, decodes to the class that
+    // writes it back byte for byte.
+    #[test]
+    fn typed_lists_round_trip() {
+        let _r = session();
+        let values = vec![
+            Value::LSTU8(vec![0, 7, 255]),
+            Value::LSTI8(vec![std::i8::MIN, -127, 0, 127]),
+            Value::LSTU16(vec![0, 65534, std::u16::MAX]),
+            Value::LSTI16(vec![std::i16::MIN, -32767, 32767]),
+            Value::LSTU32(vec![0, 4294967294, std::u32::MAX]),
+            Value::LSTI32(vec![std::i32::MIN, -2147483647, 2147483647]),
+            Value::LSTI64(vec![std::i64::MIN, -(1 << 53), 1 << 53]),
+            Value::LSTU64(vec![0, 1 << 53, std::u64::MAX]),
+            Value::LSTF32(vec![1.5, -0.25, 3.4e38, std::f32::INFINITY]),
+            Value::LSTF64(vec![1.5, -0.0, 1e300, std::f64::NEG_INFINITY]),
+        ];
+        let deser = RTsonDeserializer::new();
+        for value in values {
+            let bytes = encode(&value).unwrap();
+            let x = preserve(deser.read_slice(&bytes).unwrap());
+            assert_eq!(::raw_bytes(&::to_tson(x).unwrap()), &bytes[..], "{:?}", value);
+        }
+        let bytes = tson("c('a', '\u00e9', '')");
+        let x = preserve(deser.read_slice(&bytes).unwrap());
+        assert_eq!(::raw_bytes(&::to_tson(x).unwrap()), &bytes[..]);
+    }
+
+    #[test]
+    fn sort_keys() {
+        let _r = session();
+        let options = DecodeOptions { sort_keys: true, ..DecodeOptions::default() };
+        let deser = RTsonDeserializer::with_options(options.clone());
+        let bytes = tson("list(b = 1, a = list(d = 'x', c = 'y'), c = 3)");
+        let x = preserve(deser.read_slice(&bytes).unwrap());
+        let expected = "list(a = structure(list(c = 'y', d = 'x'), class = 'tsonmap'), b = 1, c = 3)";
+        assert!(identical(x, r(&format!("structure({}, class = 'tsonmap')", expected))));
+        // in the order the JSON decoder gives
+        let json = ::to_json(r("list(b = 1, a = list(d = 'x', c = 'y'), c = 3)")).unwrap();
+        let y = preserve(::from_json_with(&json, &options).unwrap());
+        let names = |x| ::strings(unsafe { Rf_getAttrib(x, R_NamesSymbol) }).unwrap();
+        assert_eq!(names(x), names(y));
+        // the document order otherwise
+        let x = preserve(RTsonDeserializer::new().read_slice(&bytes).unwrap());
+        assert!(identical(x, r("structure(list(b = 1, a = structure(list(d = 'x', c = 'y'), class = 'tsonmap'), c = 3), \
+                                class = 'tsonmap')")));
+    }
+
+    #[test]
+    fn bulk_lists_match_per_element() {
+        let _r = session();
+        let deser = RTsonDeserializer::new();
+
+        let bytes = tson("c(1.5, NA, -Inf, NaN, 2, -0, 1e300)");
+        let values = Vec::<f64>::rnew(deser.read_slice(&bytes).unwrap()).unwrap();
+        let expected: Vec<u64> = payload(&bytes, 7, 8).iter()
+            .map(|x| u64::from_le_bytes([x[0], x[1], x[2], x[3], x[4], x[5], x[6], x[7]]))
+            .collect();
+        assert_eq!(values.iter().map(|x| x.to_bits()).collect::<Vec<_>>(), expected);
+
+        let bytes = tson("c(1L, NA, -3L, 2147483647L)");
+        let values = Vec::<i32>::rnew(deser.read_slice(&bytes).unwrap()).unwrap();
+        let expected: Vec<i32> = payload(&bytes, 4, 4).iter()
+            .map(|x| i32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+            .collect();
+        assert_eq!(values, expected);
+
+        for code in &["numeric(0)", "integer(0)", "as.raw(0:255)", "raw(0)", "seq(0, 1, length.out = 1001)"] {
+            let bytes = tson(code);
+            let by_reader = deser.read(&mut Cursor::new(&bytes[..])).unwrap();
+            assert!(identical(by_reader, r(code)), "{}", code);
+            assert!(identical(deser.read_slice(&bytes).unwrap(), r(code)), "{}", code);
+        }
+    }
+
+    // cargo test --release -- --ignored --nocapture bench_
+    #[test]
+    #[ignore]
+    fn bench_decode_10m() {
+        let _r = session();
+        let len = 10_000_000;
+        let bytes = tson("as.double(1:1e7)");
+        let deser = RTsonDeserializer::new();
+
+        // the decoding bulk copies replaced, one read_f64 per element
+        let start = std::time::Instant::now();
+        let mut cursor = Cursor::new(&bytes[bytes.len() - len * 8..]);
+        let reader: &mut dyn Reader = &mut cursor;
+        let mut values = NumVec::alloc(len);
+        for i in 0..len {
+            unsafe { values.uset(i, reader.read_f64().unwrap()) };
+        }
+        let per_element = start.elapsed();
+
+        let start = std::time::Instant::now();
+        deser.read_slice(&bytes).unwrap();
+        let bulk = start.elapsed();
+
+        let start = std::time::Instant::now();
+        deser.read(&mut Cursor::new(&bytes[..])).unwrap();
+        let by_reader = start.elapsed();
+        println!("float64 list of {} : per element {:?}, bulk {:?}, reader {:?}", len, per_element, bulk, by_reader);
+    }
+}
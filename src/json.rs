@@ -0,0 +1,924 @@
+use super::*;
+use std::fmt::Write;
+
+// JSON encoding and decoding of R objects.
+//
+// Encoding walks the R object and writes JSON text as it goes, with the
+// same class dispatch as the TSON encoder, instead of building a
+// rustson::Value copy of the whole object first.
+//
+// rustson decodes objects into a HashMap, which loses the key order, so
+// JSON is parsed here into a tree keeping object members in document
+// order before being converted to R.
+
+#[derive(Debug, PartialEq)]
+enum JsonValue {
+    Null,
+    Bool(bool),
+    Int(i32),
+    Double(f64),
+    Str(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+pub fn decode_json_r(data: &[u8], options: &DecodeOptions) -> RTsonResult<SEXP> {
+    let mut parser = JsonParser::new(data, options.limits.max_depth);
+    let value = parser.parse_document()?;
+    json_to_r(&value, options)
+}
+
+fn json_to_r(value: &JsonValue, options: &DecodeOptions) -> RTsonResult<SEXP> {
+    match *value {
+        JsonValue::Null => Ok(().intor()?),
+        JsonValue::Bool(v) => Ok(v.intor()?),
+        JsonValue::Int(v) => Ok(tag_scalar(v.intor()?, options)?),
+        JsonValue::Double(v) => Ok(tag_scalar(v.intor()?, options)?),
+        JsonValue::Str(ref v) => Ok(tag_scalar(utf8_strings(&[v])?, options)?),
+        JsonValue::Array(ref v) => {
+            let mut lst = RList::alloc(v.len());
+            for (i, obj) in v.iter().enumerate() {
+                lst.set(i, json_to_r(obj, options)?)?;
+            }
+            Ok(lst.intor()?)
+        }
+        JsonValue::Object(ref v) => {
+            let mut members: Vec<&(String, JsonValue)> = v.iter().collect();
+            if options.sort_keys {
+                members.sort_by(|a, b| a.0.cmp(&b.0));
+            }
+
+            let names = CharVec::alloc(members.len());
+            let mut values = RList::alloc(members.len());
+            let mut keys = Vec::with_capacity(members.len());
+            for (i, &&(ref k, ref obj)) in members.iter().enumerate() {
+                unsafe {
+                    SET_STRING_ELT(names.s(), i as R_xlen_t, mk_char_utf8(k));
+                }
+                values.set(i, json_to_r(obj, options)?)?;
+                keys.push(k.clone());
+            }
+
+            if rtype::is_rtype(&keys) {
+                return rtype::rtype_to_r(&keys, &values, true);
+            }
+
+            unsafe {
+                Rf_setAttrib(values.s(), R_NamesSymbol, names.s());
+            }
+            Ok(tag_map(values.intor()?, options)?)
+        }
+    }
+}
+
+struct JsonParser<'a> {
+    data: &'a [u8],
+    pos: usize,
+    // Nesting of the arrays and objects being parsed, bounded so that deep
+    // input fails instead of overflowing the stack.
+    depth: usize,
+    max_depth: Option<usize>,
+}
+
+impl<'a> JsonParser<'a> {
+    fn new(data: &'a [u8], max_depth: Option<usize>) -> JsonParser<'a> {
+        JsonParser { data, pos: 0, depth: 0, max_depth }
+    }
+
+    fn parse_document(&mut self) -> RTsonResult<JsonValue> {
+        let value = self.parse_value()?;
+        self.skip_whitespace();
+        if self.pos != self.data.len() {
+            return Err(self.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    fn error(&self, msg: &str) -> RTsonError {
+        RTsonError::new(format!("json : {} at position {}", msg, self.pos))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.data.get(self.pos).cloned()
+    }
+
+    fn next_byte(&mut self) -> RTsonResult<u8> {
+        match self.peek() {
+            Some(c) => {
+                self.pos += 1;
+                Ok(c)
+            }
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> RTsonResult<()> {
+        if self.next_byte()? != c {
+            self.pos -= 1;
+            return Err(self.error(&format!("expected '{}'", c as char)));
+        }
+        Ok(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                b' ' | b'\t' | b'\n' | b'\r' => self.pos += 1,
+                _ => break,
+            }
+        }
+    }
+
+    fn parse_value(&mut self) -> RTsonResult<JsonValue> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.parse_object(),
+            Some(b'[') => self.parse_array(),
+            Some(b'"') => Ok(JsonValue::Str(self.parse_string()?)),
+            Some(b't') => self.parse_literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.parse_literal("false", JsonValue::Bool(false)),
+            Some(b'n') => self.parse_literal("null", JsonValue::Null),
+            Some(b'-') | Some(b'0'..=b'9') => self.parse_number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    fn parse_literal(&mut self, literal: &str, value: JsonValue) -> RTsonResult<JsonValue> {
+        if self.data[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(value)
+        } else {
+            Err(self.error("unexpected character"))
+        }
+    }
+
+    fn enter(&mut self) -> RTsonResult<()> {
+        self.depth += 1;
+        match self.max_depth {
+            Some(max) if self.depth > max => Err(self.error(&format!("limit : depth above {}", max))),
+            _ => Ok(()),
+        }
+    }
+
+    fn parse_object(&mut self) -> RTsonResult<JsonValue> {
+        self.enter()?;
+        let value = self.parse_members();
+        self.depth -= 1;
+        value
+    }
+
+    fn parse_members(&mut self) -> RTsonResult<JsonValue> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(JsonValue::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            let value = self.parse_value()?;
+            members.push((key, value));
+            self.skip_whitespace();
+            match self.next_byte()? {
+                b',' => {}
+                b'}' => return Ok(JsonValue::Object(members)),
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("expected ',' or '}'"));
+                }
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> RTsonResult<JsonValue> {
+        self.enter()?;
+        let value = self.parse_elements();
+        self.depth -= 1;
+        value
+    }
+
+    fn parse_elements(&mut self) -> RTsonResult<JsonValue> {
+        self.expect(b'[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(JsonValue::Array(values));
+        }
+        loop {
+            values.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.next_byte()? {
+                b',' => {}
+                b']' => return Ok(JsonValue::Array(values)),
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("expected ',' or ']'"));
+                }
+            }
+        }
+    }
+
+    // Skips the digits at the current position, returning how many.
+    fn skip_digits(&mut self) -> usize {
+        let start = self.pos;
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+        self.pos - start
+    }
+
+    // -?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?
+    fn parse_number(&mut self) -> RTsonResult<JsonValue> {
+        let start = self.pos;
+        let bad_number = |parser: &mut Self| {
+            parser.pos = start;
+            Err(parser.error("bad number"))
+        };
+
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        if self.peek() == Some(b'0') {
+            self.pos += 1;
+            if let Some(b'0'..=b'9') = self.peek() {
+                return bad_number(self);
+            }
+        } else if self.skip_digits() == 0 {
+            return bad_number(self);
+        }
+
+        let mut integer = true;
+        if self.peek() == Some(b'.') {
+            integer = false;
+            self.pos += 1;
+            if self.skip_digits() == 0 {
+                return bad_number(self);
+            }
+        }
+        if let Some(b'e') | Some(b'E') = self.peek() {
+            integer = false;
+            self.pos += 1;
+            if let Some(b'+') | Some(b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if self.skip_digits() == 0 {
+                return bad_number(self);
+            }
+        }
+
+        // the bytes are ASCII digits and signs, so valid UTF-8
+        let text = std::str::from_utf8(&self.data[start..self.pos]).unwrap();
+        if integer {
+            if let Ok(v) = text.parse::<i32>() {
+                if v != NA_INTEGER {
+                    return Ok(JsonValue::Int(v));
+                }
+            }
+        }
+        match text.parse::<f64>() {
+            Ok(v) if v.is_finite() => Ok(JsonValue::Double(v)),
+            _ => bad_number(self),
+        }
+    }
+
+    fn parse_hex4(&mut self) -> RTsonResult<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let c = self.next_byte()?;
+            let digit = match (c as char).to_digit(16) {
+                Some(d) => d,
+                None => return Err(self.error("bad unicode escape")),
+            };
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn parse_string(&mut self) -> RTsonResult<String> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            match self.next_byte()? {
+                b'"' => break,
+                b'\\' => {
+                    match self.next_byte()? {
+                        b'"' => bytes.push(b'"'),
+                        b'\\' => bytes.push(b'\\'),
+                        b'/' => bytes.push(b'/'),
+                        b'b' => bytes.push(8),
+                        b'f' => bytes.push(12),
+                        b'n' => bytes.push(b'\n'),
+                        b'r' => bytes.push(b'\r'),
+                        b't' => bytes.push(b'\t'),
+                        b'u' => {
+                            let mut code = self.parse_hex4()?;
+                            if code == 0 {
+                                // R strings cannot hold NUL
+                                return Err(self.error("embedded nul in string"));
+                            }
+                            if (0xD800..0xDC00).contains(&code) {
+                                // high surrogate, must be followed by a low one
+                                self.expect(b'\\')?;
+                                self.expect(b'u')?;
+                                let low = self.parse_hex4()?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return Err(self.error("bad unicode escape"));
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            match std::char::from_u32(code) {
+                                Some(ch) => {
+                                    let mut utf8 = [0; 4];
+                                    bytes.extend_from_slice(ch.encode_utf8(&mut utf8).as_bytes());
+                                }
+                                None => return Err(self.error("bad unicode escape")),
+                            }
+                        }
+                        _ => return Err(self.error("bad escape")),
+                    }
+                }
+                c if c < 0x20 => {
+                    self.pos -= 1;
+                    return Err(self.error("control character in string"));
+                }
+                c => bytes.push(c),
+            }
+        }
+
+        match String::from_utf8(bytes) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(self.error("bad string")),
+        }
+    }
+}
+
+// The text with the messages of the precision lost, as an R character
+// vector or NULL, which the caller warns about.
+pub fn encode_json_r(object: SEXP, options: &EncodeOptions) -> RTsonResult<(String, SEXP)> {
+    let mut writer = JsonWriter { options, losses: Losses::new(options.strict), out: String::new() };
+    writer.add_object(object)?;
+    let warnings = writer.losses.take(object)?;
+    Ok((writer.out, warnings))
+}
+
+struct JsonWriter<'a> {
+    options: &'a EncodeOptions,
+    losses: Losses,
+    out: String,
+}
+
+impl<'a> JsonWriter<'a> {
+    fn add_object(&mut self, object: SEXP) -> RTsonResult<()> {
+        if self.options.preserve_attributes {
+            let attributes = extra_attributes(object)?;
+            if !attributes.is_empty() {
+                self.begin_rtype(RTYPE_ATTRIBUTES);
+                self.add_key(RTYPE_DATA_KEY);
+                self.add_array(object)?;
+                self.add_key(ATTRIBUTES_KEY);
+                self.out.push('{');
+                for (i, (name, value)) in attributes.into_iter().enumerate() {
+                    if i > 0 {
+                        self.out.push(',');
+                    }
+                    self.add_string(&name);
+                    self.out.push(':');
+                    self.add_object(value)?;
+                }
+                self.out.push_str("}}");
+                return Ok(());
+            }
+        }
+        self.add_array(object)
+    }
+
+    fn add_array(&mut self, object: SEXP) -> RTsonResult<()> {
+        if let Some(dim) = array_dim(object)? {
+            self.begin_rtype(RTYPE_ARRAY);
+            self.add_key(RTYPE_DATA_KEY);
+            self.add_vector(object)?;
+            self.add_key(DIM_KEY);
+            self.add_list(dim.iter(), |w, x| w.add_int(*x));
+            let dimnames = unsafe { Rf_getAttrib(object, R_DimNamesSymbol) };
+            if dimnames.rtype() != NILSXP {
+                self.add_key(DIMNAMES_KEY);
+                self.add_object(dimnames)?;
+            }
+            self.out.push('}');
+            return Ok(());
+        }
+        self.add_vector(object)
+    }
+
+    fn add_vector(&mut self, object: SEXP) -> RTsonResult<()> {
+        match vector_kind(object, self.options)? {
+            Kind::Null => self.out.push_str("null"),
+            Kind::Raw => {
+                let values = RawVec::rnew(object)?;
+                self.add_list(values.into_iter(), |w, x| w.add_int(x));
+            }
+            Kind::Time(rtype) => {
+                self.begin_rtype(rtype);
+                self.add_key(RTYPE_DATA_KEY);
+                if object.rtype() == REALSXP {
+                    let values = real_slice(&object);
+                    check_na(self.options, rtype, values, |x| is_na_real(*x))?;
+                    self.add_reals(values);
+                } else {
+                    let values = int_slice(&object);
+                    check_na(self.options, rtype, values, |x| *x == NA_INTEGER)?;
+                    self.add_ints(values);
+                }
+                for key in &[TZONE_KEY, UNITS_KEY] {
+                    if let Some(attr) = get_attrib(object, key)? {
+                        self.add_key(key);
+                        self.add_object(attr)?;
+                    }
+                }
+                self.out.push('}');
+            }
+            Kind::Integer64 => {
+                let values = real_slice(&object);
+                check_na(self.options, "integer64", values, |x| x.to_bits() as i64 == NA_INTEGER64)?;
+                self.add_list(values.iter(), |w, x| match x.to_bits() as i64 {
+                    NA_INTEGER64 => w.out.push_str("null"),
+                    x => w.add_int(x),
+                });
+            }
+            Kind::Real(clazz) => {
+                let values = real_slice(&object);
+                check_na(self.options, "real", values, |x| is_na_real(*x))?;
+                if clazz == Some("scalar") {
+                    check_scalar("real", values.len())?;
+                    self.add_real(values[0]);
+                } else if clazz == Some("uint64") {
+                    self.add_cast_reals(values, "uint64", -1.0, std::u64::MAX as f64, |x| x as u64)?;
+                } else if clazz == Some("int64") {
+                    self.add_cast_reals(values, "int64", std::i64::MIN as f64, std::i64::MAX as f64, |x| x as i64)?;
+                } else if clazz == Some("uint32") {
+                    self.add_cast_reals(values, "uint32", -1.0, std::u32::MAX as f64, |x| x as u32)?;
+                } else if clazz == Some("float32") {
+                    self.add_list(values.iter(), |w, x| w.add_float32(real_to_f32(*x)));
+                } else {
+                    self.add_list(values.iter(), |w, x| w.add_real(*x));
+                }
+            }
+            Kind::Factor => {
+                if self.options.expand_factors {
+                    return Ok(factor_labels(object, |labels| Ok(self.add_object(labels)?))?);
+                }
+
+                self.begin_rtype(RTYPE_FACTOR);
+                self.add_key(RTYPE_DATA_KEY);
+                let codes = int_slice(&object);
+                check_na(self.options, "factor", codes, |x| *x == NA_INTEGER)?;
+                self.add_ints(codes);
+                self.add_key(FACTOR_LEVELS_KEY);
+                self.add_object(unsafe { Rf_getAttrib(object, R_LevelsSymbol) })?;
+                self.add_key(FACTOR_ORDERED_KEY);
+                self.out.push_str(if inherits(object, "ordered")? { "true" } else { "false" });
+                self.out.push('}');
+            }
+            Kind::Int(clazz) => {
+                let values = int_slice(&object);
+                check_na(self.options, "int", values, |x| *x == NA_INTEGER)?;
+                if clazz == Some("scalar") {
+                    check_scalar("int", values.len())?;
+                    self.add_int_or_null(values[0]);
+                } else {
+                    match clazz {
+                        Some("int8") => self.add_converted_ints::<i8>(values, "int8")?,
+                        Some("int16") => self.add_converted_ints::<i16>(values, "int16")?,
+                        Some("int64") => self.add_converted_ints::<i64>(values, "int64")?,
+                        Some("uint8") => self.add_converted_ints::<u8>(values, "uint8")?,
+                        Some("uint16") => self.add_converted_ints::<u16>(values, "uint16")?,
+                        Some("uint64") => self.add_converted_ints::<u64>(values, "uint64")?,
+                        Some("uint32") => self.add_converted_ints::<u32>(values, "uint32")?,
+                        _ => self.add_ints(values),
+                    }
+                }
+            }
+            Kind::Logical => {
+                let values = logical_slice(&object);
+                check_na(self.options, "bool", values, |x| *x == NA_LOGICAL)?;
+                if values.len() == 1 && values[0] != NA_LOGICAL {
+                    self.add_bool(values[0]);
+                } else {
+                    self.add_list(values.iter(), |w, x| w.add_bool(*x));
+                }
+            }
+            Kind::Complex => {
+                let values = complex_slice(&object);
+                if self.options.na == NaPolicy::Error {
+                    if let Some(i) = first_na_complex(values) {
+                        return Err(na_error("complex", i));
+                    }
+                }
+
+                let na = self.options.na;
+                self.begin_rtype(RTYPE_COMPLEX);
+                self.add_key(RTYPE_DATA_KEY);
+                self.add_list(values.chunks(2), |w, x| {
+                    if na == NaPolicy::Null && (is_na_real(x[0]) || is_na_real(x[1])) {
+                        w.out.push_str("null");
+                    } else {
+                        w.out.push('{');
+                        w.add_string(COMPLEX_RE_KEY);
+                        w.out.push(':');
+                        w.add_real(x[0]);
+                        w.out.push(',');
+                        w.add_string(COMPLEX_IM_KEY);
+                        w.out.push(':');
+                        w.add_real(x[1]);
+                        w.out.push('}');
+                    }
+                });
+                self.out.push('}');
+            }
+            Kind::DataFrame => {
+                let rlist = RList::new(object)?;
+                let names: CharVec = RName::name(&rlist);
+                let (nrow, row_names) = data_frame_rows(object);
+                self.begin_rtype(RTYPE_DATA_FRAME);
+                self.add_key(DF_NROW_KEY);
+                self.add_int(nrow as i32);
+                self.add_key(DF_COLUMNS_KEY);
+                self.add_map(object, &names, rlist.rsize() as usize, column_names_policy(self.options.names))?;
+                if let Some(row_names) = row_names {
+                    self.add_key(DF_ROW_NAMES_KEY);
+                    self.add_object(row_names)?;
+                }
+                if nrow == 0 {
+                    self.add_key(DF_TYPES_KEY);
+                    self.add_column_types(object, &names, rlist.rsize() as usize)?;
+                }
+                self.out.push('}');
+            }
+            Kind::Map => {
+                let rlist = RList::new(object)?;
+                let names: CharVec = RName::name(&rlist);
+                self.add_map(object, &names, rlist.rsize() as usize, self.options.names)?;
+            }
+            Kind::List => {
+                let len = RList::new(object)?.rsize() as usize;
+                self.out.push('[');
+                for i in 0..len {
+                    if i > 0 {
+                        self.out.push(',');
+                    }
+                    self.add_object(list_elt(object, i))?;
+                }
+                self.out.push(']');
+            }
+            Kind::Language => {
+                let text = deparse_language(object)?;
+                self.begin_rtype(RTYPE_LANGUAGE);
+                self.add_key(RTYPE_DATA_KEY);
+                self.add_string(&text);
+                self.out.push('}');
+            }
+            Kind::Environment => {
+                let name = environment_name(object)?;
+                self.begin_rtype(RTYPE_ENVIRONMENT);
+                self.add_key(RTYPE_DATA_KEY);
+                self.add_string(name);
+                self.out.push('}');
+            }
+            Kind::Str(scalar) => {
+                let len = unsafe { Rf_xlength(object) as usize };
+                if self.options.na == NaPolicy::Error {
+                    if let Some(&i) = string_nas(object).first() {
+                        return Err(na_error("str", i));
+                    }
+                }
+                if scalar {
+                    check_scalar("str", len)?;
+                }
+
+                // NA is written as null whatever the policy, JSON having no
+                // string sentinel
+                if !scalar {
+                    self.out.push('[');
+                }
+                for i in 0..len {
+                    if i > 0 {
+                        self.out.push(',');
+                    }
+                    if is_na_string(object, i) {
+                        self.out.push_str("null");
+                    } else {
+                        let value = string_elt(object, i)?;
+                        self.add_string(&value);
+                    }
+                }
+                if !scalar {
+                    self.out.push(']');
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Writes the elements of a named list as an object, in list order.
+    fn add_map(&mut self, object: SEXP, names: &CharVec, len: usize, policy: NamesPolicy) -> RTsonResult<()> {
+        let keys = map_keys(names, len, policy)?;
+        self.out.push('{');
+        for (i, (index, key)) in keys.into_iter().enumerate() {
+            if i > 0 {
+                self.out.push(',');
+            }
+            self.add_string(&key);
+            self.out.push(':');
+            self.add_object(list_elt(object, index))?;
+        }
+        self.out.push('}');
+        Ok(())
+    }
+
+    // Writes the R type of the atomic columns of a data.frame, which its
+    // empty columns do not carry.
+    fn add_column_types(&mut self, object: SEXP, names: &CharVec, len: usize) -> RTsonResult<()> {
+        let keys = map_keys(names, len, column_names_policy(self.options.names))?;
+        self.out.push('{');
+        let mut first = true;
+        for (index, key) in keys {
+            let rtype = match atomic_type_name(list_elt(object, index).rtype()) {
+                Some(rtype) => rtype,
+                None => continue,
+            };
+            if !first {
+                self.out.push(',');
+            }
+            first = false;
+            self.add_string(&key);
+            self.out.push(':');
+            self.add_string(rtype);
+        }
+        self.out.push('}');
+        Ok(())
+    }
+
+    fn add_list<I, F>(&mut self, values: I, mut f: F)
+        where I: Iterator, F: FnMut(&mut Self, I::Item) {
+        self.out.push('[');
+        for (i, x) in values.enumerate() {
+            if i > 0 {
+                self.out.push(',');
+            }
+            f(self, x);
+        }
+        self.out.push(']');
+    }
+
+    // Plain doubles and integers. JSON has no sentinels, so NA is null.
+    fn add_reals(&mut self, values: &[f64]) {
+        self.add_list(values.iter(), |w, x| w.add_real(*x));
+    }
+
+    fn add_ints(&mut self, values: &[i32]) {
+        self.add_list(values.iter(), |w, x| w.add_int_or_null(*x));
+    }
+
+    // Writes R integers converted to the integer type `clazz` names, failing
+    // on values out of its range as TSON does. JSON needs no NA sentinel,
+    // NA is null.
+    fn add_converted_ints<T>(&mut self, values: &[i32], clazz: &str) -> RTsonResult<()>
+        where T: TryFrom<i32> + fmt::Display {
+        let mut converted = Vec::<Option<T>>::with_capacity(values.len());
+        for (i, x) in values.iter().enumerate() {
+            converted.push(if *x == NA_INTEGER { None } else { Some(convert_int(*x, clazz, i)?) });
+        }
+        self.add_list(converted.into_iter(), |w, x| match x {
+            Some(x) => w.add_int(x),
+            None => w.out.push_str("null"),
+        });
+        Ok(())
+    }
+
+    // Writes R doubles holding integers as `cast` converts them, checking
+    // for precision loss as TSON does; NA is null.
+    fn add_cast_reals<T, F>(&mut self, values: &[f64], clazz: &str, lo: f64, hi: f64, cast: F) -> RTsonResult<()>
+        where T: fmt::Display, F: Fn(f64) -> T {
+        self.losses.check("real", clazz, values.iter().map(|x| real_cast_lossy(*x, lo, hi)))?;
+        self.add_list(values.iter(), |w, x| if x.is_nan() {
+            w.out.push_str("null")
+        } else {
+            w.add_int(cast(*x))
+        });
+        Ok(())
+    }
+
+    // Opens a reserved map tagged with `rtype`; the caller writes the other
+    // members, each starting with add_key, and closes it.
+    fn begin_rtype(&mut self, rtype: &str) {
+        self.out.push('{');
+        self.add_string(RTYPE_KEY);
+        self.out.push(':');
+        self.add_string(rtype);
+    }
+
+    fn add_key(&mut self, key: &str) {
+        self.out.push(',');
+        self.add_string(key);
+        self.out.push(':');
+    }
+
+    fn add_int<T: fmt::Display>(&mut self, value: T) {
+        write!(self.out, "{}", value).unwrap();
+    }
+
+    fn add_int_or_null(&mut self, value: i32) {
+        if value == NA_INTEGER {
+            self.out.push_str("null");
+        } else {
+            self.add_int(value);
+        }
+    }
+
+    // JSON has no NaN or infinity, so NA and every non-finite value is null.
+    fn add_real(&mut self, value: f64) {
+        if value.is_finite() {
+            write!(self.out, "{:?}", value).unwrap();
+        } else {
+            self.out.push_str("null");
+        }
+    }
+
+    fn add_float32(&mut self, value: f32) {
+        if value.is_finite() {
+            write!(self.out, "{:?}", value).unwrap();
+        } else {
+            self.out.push_str("null");
+        }
+    }
+
+    fn add_bool(&mut self, value: i32) {
+        self.out.push_str(if value == NA_LOGICAL {
+            "null"
+        } else if value != 0 {
+            "true"
+        } else {
+            "false"
+        });
+    }
+
+    fn add_string(&mut self, value: &str) {
+        self.out.push('"');
+        for ch in value.chars() {
+            match ch {
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                '\n' => self.out.push_str("\\n"),
+                '\r' => self.out.push_str("\\r"),
+                '\t' => self.out.push_str("\\t"),
+                c if (c as u32) < 0x20 => write!(self.out, "\\u{:04x}", c as u32).unwrap(),
+                c => self.out.push(c),
+            }
+        }
+        self.out.push('"');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutil::*;
+
+    fn round_trip(code: &str) {
+        let _r = session();
+        let x = r(code);
+        let json = to_json(x).unwrap();
+        let y = from_json(&json).unwrap();
+        assert!(identical(x, y), "{} does not round trip through {}", code, json);
+    }
+
+    fn parse(text: &str) -> RTsonResult<JsonValue> {
+        JsonParser::new(text.as_bytes(), Some(1000)).parse_document()
+    }
+
+    fn parse_error(text: &str) -> String {
+        parse(text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn parse_values() {
+        assert_eq!(parse(" null ").unwrap(), JsonValue::Null);
+        assert_eq!(parse("[true,false]").unwrap(),
+                   JsonValue::Array(vec![JsonValue::Bool(true), JsonValue::Bool(false)]));
+        assert_eq!(parse(r#"{"b":1,"a":{}}"#).unwrap(), JsonValue::Object(vec![
+            ("b".to_string(), JsonValue::Int(1)),
+            ("a".to_string(), JsonValue::Object(vec![])),
+        ]));
+        assert_eq!(parse(r#""a\"\\\n\u00e9\ud83d\ude00""#).unwrap(),
+                   JsonValue::Str("a\"\\\n\u{e9}\u{1f600}".to_string()));
+    }
+
+    #[test]
+    fn parse_numbers() {
+        assert_eq!(parse("0").unwrap(), JsonValue::Int(0));
+        assert_eq!(parse("-12").unwrap(), JsonValue::Int(-12));
+        assert_eq!(parse("1.5e2").unwrap(), JsonValue::Double(150.0));
+        assert_eq!(parse("-0.25").unwrap(), JsonValue::Double(-0.25));
+        assert_eq!(parse("1E-2").unwrap(), JsonValue::Double(0.01));
+        assert_eq!(parse("3000000000").unwrap(), JsonValue::Double(3e9));
+        assert_eq!(parse("-2147483648").unwrap(), JsonValue::Double(-2147483648.0));
+        for text in &["01", "-", "1.", ".5", "1e", "+1", "-01", "1e400", "0x10"] {
+            assert!(parse(text).is_err(), "{} parsed", text);
+        }
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(parse_error("[1,]"), "json : unexpected character at position 3");
+        assert_eq!(parse_error("[1 2]"), "json : expected ',' or ']' at position 3");
+        assert_eq!(parse_error("1 2"), "json : trailing characters at position 2");
+        assert_eq!(parse_error("\"a\nb\""), "json : control character in string at position 2");
+        assert_eq!(parse_error(r#""\u0000""#), "json : embedded nul in string at position 7");
+        assert_eq!(parse_error(r#""\ud800x""#), "json : expected '\\' at position 7");
+        let bytes = JsonParser::new(b"\"\xff\"", None).parse_document();
+        assert_eq!(bytes.unwrap_err().to_string(), "json : bad string at position 3");
+    }
+
+    #[test]
+    fn parse_depth() {
+        let deep = "[".repeat(100_000);
+        assert_eq!(parse_error(&deep), "json : limit : depth above 1000 at position 1000");
+        let nested = format!("{}{}", "[".repeat(1000), "]".repeat(1000));
+        assert!(parse(&nested).is_ok());
+        let nested = format!("{}{}", "{\"a\":".repeat(1001), "}".repeat(1001));
+        assert!(parse(&nested).is_err());
+    }
+
+    #[test]
+    fn factor_round_trip() {
+        round_trip("factor(c('b', 'a', 'b'), levels = c('b', 'a'))");
+        round_trip("factor(c('b', NA, 'a'))");
+        round_trip("factor(c('lo', 'hi'), levels = c('lo', 'hi'), ordered = TRUE)");
+        round_trip("factor('a')");
+        round_trip("factor(character(0))");
+    }
+
+    #[test]
+    fn factor_sentinel_code() {
+        let _r = session();
+        let json = r#"{"__rtype__":"factor","data":[1,-2147483648],"levels":["a"],"ordered":false}"#;
+        assert!(identical(from_json(json).unwrap(), r("factor(c('a', NA))")));
+    }
+
+    #[test]
+    fn time_round_trip() {
+        round_trip("as.Date(c('2020-01-31', NA))");
+        round_trip("as.POSIXct(c(0, 1.5), origin = '1970-01-01', tz = 'UTC')");
+        round_trip("as.difftime(c(1, 2.5), units = 'hours')");
+        round_trip("as.Date(character(0))");
+    }
+
+    #[test]
+    fn array_round_trip() {
+        round_trip("matrix(1:6, 2)");
+        round_trip("matrix(1:4, 2, dimnames = list(c('a', 'b'), c('x', 'y')))");
+        round_trip("matrix(c(1.5, NA), 1, dimnames = list('a', NULL))");
+        round_trip("array(1:8, c(2, 2, 2), dimnames = list(r = c('a', 'b'), c = c('x', 'y'), NULL))");
+    }
+
+    #[test]
+    fn data_frame_round_trip() {
+        round_trip("data.frame(x = 1:2, y = c('a', NA), z = c(TRUE, NA), stringsAsFactors = FALSE)");
+        round_trip("data.frame(x = c(1.5, 2), row.names = c('a', 'b'))");
+        round_trip("data.frame(x = 1, row.names = 'a')");
+        round_trip("data.frame(x = integer(0), y = character(0), z = logical(0), w = numeric(0))");
+        round_trip("data.frame(f = factor(character(0)), d = as.Date(character(0)))");
+    }
+
+    #[test]
+    fn complex_round_trip() {
+        round_trip("complex(real = c(1, -2.5), imaginary = c(0, 3))");
+        round_trip("complex(real = NA_real_, imaginary = 1)");
+        round_trip("c(1i, NA)");
+    }
+
+    #[test]
+    fn integer_na_is_null() {
+        round_trip("data.frame(x = c(1L, NA), y = factor(c(NA, 'a')))");
+        let _r = session();
+        assert_eq!(to_json(r("c(1L, NA)")).unwrap(), "[1,null]");
+        assert_eq!(to_json(r("NA_integer_")).unwrap(), "[null]");
+        assert_eq!(to_json(r("structure(c(-128L, NA), class = 'int8')")).unwrap(), "[-128,null]");
+        let fails = |object, options: &EncodeOptions, msg: &str| {
+            let e = to_json_with(object, options).unwrap_err();
+            assert!(e.to_string().contains(msg), "{} != {}", e, msg);
+        };
+        let options = EncodeOptions::default();
+        fails(r("structure(c(NA, 1000L), class = 'int8')"), &options, "int8 : value 1000 out of range at index 1");
+        fails(r("structure(c(NA, -1L), class = 'uint32')"), &options, "uint32 : value -1 out of range at index 1");
+        assert_eq!(to_json(r("structure(c(NA, 2), class = 'uint32')")).unwrap(), "[null,2]");
+        let strict = EncodeOptions { strict: true, ..EncodeOptions::default() };
+        fails(r("structure(c(NA, 1.5), class = 'int64')"), &strict, "real : 1 value(s) not exact as int64, first at index 1");
+    }
+}
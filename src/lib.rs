@@ -3,21 +3,114 @@ extern crate rustson;
 
 use std::error;
 use std::fmt;
-use std::convert::{From};
+use std::convert::{From, TryFrom};
 
 use rustr::*;
 use rustson::*;
 
 use std::io::Cursor;
-use std::collections::HashMap;
+use std::collections::HashSet;
+use std::cell::RefCell;
 use ::std::ffi::*;
 
 pub mod ser;
 pub mod deser;
+pub mod options;
+mod rtype;
+mod json;
+#[cfg(test)]
+mod testutil;
 
 
 use deser::RTsonDeserializer;
 use ser::RSerializer;
+pub use options::*;
+
+// R objects that TSON has no native type for are written as a map holding
+// the R type name under `RTYPE_KEY` and the payload under `RTYPE_DATA_KEY`.
+pub const RTYPE_KEY: &str = "__rtype__";
+pub const RTYPE_DATA_KEY: &str = "data";
+
+// Logical vectors that are not a single TRUE or FALSE; data is a uint8
+// list where 0 is FALSE, 1 is TRUE and LOGICAL_NA_U8 is NA.
+pub const RTYPE_LOGICAL: &str = "logical";
+pub const LOGICAL_NA_U8: u8 = 255;
+
+// Character vectors holding NA; data is the string list with NA written as
+// an empty string and RTYPE_NA_KEY the int32 list of NA positions.
+pub const RTYPE_CHARACTER: &str = "character";
+pub const RTYPE_NA_KEY: &str = "na";
+
+// data.frame, written as its row count, a map of column name to column
+// and, unless they are the automatic 1..nrow, its row names. JSON empty
+// arrays have no type, so a zero-row data.frame written as JSON also
+// carries DF_TYPES_KEY, the map of column name to R type of its atomic
+// columns.
+pub const RTYPE_DATA_FRAME: &str = "data.frame";
+pub const DF_NROW_KEY: &str = "nrow";
+pub const DF_COLUMNS_KEY: &str = "columns";
+pub const DF_ROW_NAMES_KEY: &str = "row.names";
+pub const DF_TYPES_KEY: &str = "types";
+
+// factor, data is the int32 list of codes.
+pub const RTYPE_FACTOR: &str = "factor";
+pub const FACTOR_LEVELS_KEY: &str = "levels";
+pub const FACTOR_ORDERED_KEY: &str = "ordered";
+
+// Date, POSIXct and difftime, data is the underlying double (or integer)
+// list; POSIXct keeps its `tzone` and difftime its `units` attribute.
+pub const RTYPE_DATE: &str = "Date";
+pub const RTYPE_POSIXCT: &str = "POSIXct";
+pub const RTYPE_DIFFTIME: &str = "difftime";
+pub const TZONE_KEY: &str = "tzone";
+pub const UNITS_KEY: &str = "units";
+
+// Matrices and arrays, data is the vector without its dim attribute.
+pub const RTYPE_ARRAY: &str = "array";
+pub const DIM_KEY: &str = "dim";
+pub const DIMNAMES_KEY: &str = "dimnames";
+
+// Complex vectors, data is the float64 list of interleaved real and
+// imaginary parts; in JSON a list of {re, im} maps, or null for NA.
+pub const RTYPE_COMPLEX: &str = "complex";
+pub const COMPLEX_RE_KEY: &str = "re";
+pub const COMPLEX_IM_KEY: &str = "im";
+
+// Any object in the preserve attributes mode, data is the object encoded
+// as usual and ATTRIBUTES_KEY the map of the attributes data does not
+// restore by itself, along with its class.
+pub const RTYPE_ATTRIBUTES: &str = "attributes";
+pub const ATTRIBUTES_KEY: &str = "attributes";
+
+// Calls and symbols in the preserve attributes mode, data is their text as
+// deparse gives it, parsed back without being evaluated.
+pub const RTYPE_LANGUAGE: &str = "language";
+
+// The global, base or empty environment in the preserve attributes mode,
+// as formulas refer to, data being its name. No other environment can be
+// written.
+pub const RTYPE_ENVIRONMENT: &str = "environment";
+
+extern "C" {
+    fn R_ParseVector(text: SEXP, n: std::os::raw::c_int, status: *mut std::os::raw::c_int, srcfile: SEXP) -> SEXP;
+    fn R_tryEval(expr: SEXP, env: SEXP, error: *mut std::os::raw::c_int) -> SEXP;
+    fn Rf_lang2(x: SEXP, y: SEXP) -> SEXP;
+    static R_BaseEnv: SEXP;
+    static R_EmptyEnv: SEXP;
+}
+
+// ParseStatus::PARSE_OK
+const PARSE_OK: std::os::raw::c_int = 1;
+
+const NA_INTEGER: i32 = std::i32::MIN;
+const NA_LOGICAL: i32 = NA_INTEGER;
+const NA_REAL_BITS: u64 = 0x7FF0_0000_0000_07A2;
+// Single precision has no NA, a quiet NaN with the NA_real_ low word is
+// used instead.
+const NA_FLOAT32_BITS: u32 = 0x7FC0_07A2;
+// bit64's integer64 keeps the bits of an i64 in each double, NA being the
+// same value as the int64 sentinel.
+const NA_INTEGER64: i64 = std::i64::MIN;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct RTsonError {
@@ -76,55 +169,116 @@ pub fn http_raise<T, E>(msg: E) -> RTsonResult<T>
 }
 
 pub fn to_json(object: SEXP) -> RResult<String> {
-    let value = r_to_value(object)?;
-    match encode_json(&value) {
-        Ok(v) => Ok(v),
-        Err(e) => Err(RError::other(e)),
+    to_json_with(object, &EncodeOptions::default())
+}
+
+pub fn to_json_with(object: SEXP, options: &EncodeOptions) -> RResult<String> {
+    let (json, warnings) = match json::encode_json_r(object, options) {
+        Ok(v) => v,
+        Err(e) => return Err(RError::other(e)),
+    };
+    if unsafe { Rf_xlength(warnings) } == 0 {
+        return Ok(json);
     }
+    // the text waits in an R vector while warning
+    let text = unsafe {
+        Rf_protect(warnings);
+        let text = raw_copy(json.as_bytes());
+        Rf_unprotect(1);
+        text
+    };
+    drop(json);
+    warn(warnings, text);
+    let bytes = unsafe { std::slice::from_raw_parts(RAW(text), Rf_xlength(text) as usize) };
+    Ok(String::from_utf8_lossy(bytes).into_owned())
 }
 
 pub fn from_json(data: &str) -> RResult<SEXP> {
-    match decode_json(data.as_bytes()) {
-        Ok(object) => value_to_r(&object),
+    from_json_with(data, &DecodeOptions::default())
+}
+
+pub fn from_json_with(data: &str, options: &DecodeOptions) -> RResult<SEXP> {
+    match json::decode_json_r(data.as_bytes(), options) {
+        Ok(object) => Ok(object),
         Err(e) => Err(RError::other(e)),
     }
 }
 
 pub fn to_tson(object: SEXP) -> RResult<RawVec> {
-    let ser = RSerializer::new();
-    Ok(ser.encode(&object)?)
+    to_tson_with(object, &EncodeOptions::default())
+}
+
+pub fn to_tson_with(object: SEXP, options: &EncodeOptions) -> RResult<RawVec> {
+    let (bytes, warnings) = {
+        let ser = RSerializer::with_options(options.clone());
+        let bytes = ser.encode(&object)?;
+        (bytes.s(), ser.take_warnings(bytes.s())?)
+    };
+    warn(warnings, bytes);
+    RawVec::rnew(bytes)
 }
 
 pub fn from_tson(rbytes: RawVec) -> RResult<SEXP> {
+    from_tson_with(rbytes, &DecodeOptions::default())
+}
+
+pub fn from_tson_with(rbytes: RawVec, options: &DecodeOptions) -> RResult<SEXP> {
+    let (object, warnings) = {
+        let deser = RTsonDeserializer::with_options(options.clone());
+        let object = match deser.read_slice(raw_bytes(&rbytes)) {
+            Ok(v) => v,
+            Err(e) => return Err(RError::other(e)),
+        };
+        (object, deser.take_warnings(object)?)
+    };
+    drop(rbytes);
+    warn(warnings, object);
+    Ok(object)
+}
+
+// The bytes of a raw vector, borrowed for as long as the vector is.
+fn raw_bytes(rbytes: &RawVec) -> &[u8] {
     let len = rbytes.rsize() as usize;
+    if len == 0 {
+        return &[];
+    }
+    unsafe { std::slice::from_raw_parts(RAW(rbytes.s()), len) }
+}
 
-    unsafe {
-        let rptr = RAW(rbytes.s());
-        let bytes: Vec<u8> = Vec::from_raw_parts(rptr, len, len);
+pub fn from_tson_reader(reader: &mut Cursor<&[u8]>) -> RResult<SEXP> {
+    let (object, warnings) = {
         let deser = RTsonDeserializer::new();
+        let object = deser.read_cursor(reader)?;
+        (object, deser.take_warnings(object)?)
+    };
+    warn(warnings, object);
+    Ok(object)
+}
 
-        let result;
-
-        {
-            let mut reader = Cursor::new(&bytes);
-            match deser.read(&mut reader) {
-                Ok(v) => {
-                    result = Ok(v);
-                }
-                Err(e) => {
-                    result = Err(RError::other(e));
-                }
-            }
+// A raw vector holding a copy of `bytes`.
+fn raw_copy(bytes: &[u8]) -> SEXP {
+    unsafe {
+        let object = Rf_allocVector(RAWSXP, bytes.len() as R_xlen_t);
+        if !bytes.is_empty() {
+            std::slice::from_raw_parts_mut(RAW(object), bytes.len()).copy_from_slice(bytes);
         }
-
-        std::mem::forget(bytes);
-        result
+        object
     }
 }
 
-pub fn from_tson_reader(reader: &mut Cursor<&[u8]>) -> RResult<SEXP> {
-    let deser = RTsonDeserializer::new();
-     Ok(deser.read(reader)?)
+// Raises one R warning per message of `warnings`, a character vector,
+// keeping `object` protected. A warning longjmps out under
+// options(warn = 2), so the entry points call this last, once they hold
+// no Rust value that needs dropping.
+fn warn(warnings: SEXP, object: SEXP) {
+    unsafe {
+        Rf_protect(object);
+        Rf_protect(warnings);
+        for i in 0..Rf_xlength(warnings) {
+            Rf_warning(b"%s\0".as_ptr() as *const std::os::raw::c_char, R_CHAR(STRING_ELT(warnings, i)));
+        }
+        Rf_unprotect(2);
+    }
 }
 
 
@@ -143,159 +297,885 @@ fn inherits(object: SEXP, clazz: &str) -> RResult<bool> {
     }
 }
 
-pub fn r_to_value(object: SEXP) -> RResult<Value> {
+fn set_class(object: SEXP, clazz: &str) -> RResult<SEXP> {
+    unsafe {
+        Rf_setAttrib(object, R_ClassSymbol, Rf_mkString(c_str(clazz)?.as_ptr()));
+    }
+    Ok(object)
+}
+
+fn set_classes(object: SEXP, classes: &[&str]) -> RResult<SEXP> {
+    let mut value = CharVec::alloc(classes.len());
+    for (i, clazz) in classes.iter().enumerate() {
+        value.set(i, clazz)?;
+    }
+    unsafe {
+        Rf_setAttrib(object, R_ClassSymbol, value.s());
+    }
+    Ok(object)
+}
+
+fn symbol(name: &str) -> RResult<SEXP> {
+    unsafe {
+        Ok(Rf_install(c_str(name)?.as_ptr()))
+    }
+}
+
+// The attribute `name` of `object`, None when not set.
+fn get_attrib(object: SEXP, name: &str) -> RResult<Option<SEXP>> {
+    unsafe {
+        let value = Rf_getAttrib(object, symbol(name)?);
+        if value.rtype() == NILSXP {
+            Ok(None)
+        } else {
+            Ok(Some(value))
+        }
+    }
+}
+
+// The dim attribute of an atomic vector, if any.
+fn array_dim(object: SEXP) -> RResult<Option<Vec<i32>>> {
     match object.rtype() {
-        NILSXP => Ok(Value::NULL),
-        RAWSXP => {
-            let object_ = RawVec::rnew(object)?;
-            let mut _vec = Vec::<u8>::with_capacity(object_.rsize() as usize);
-            for x in object_ {
-                _vec.push(x);
+        LGLSXP | INTSXP | REALSXP | CPLXSXP | STRSXP => {}
+        _ => return Ok(None),
+    }
+    let dim = unsafe { Rf_getAttrib(object, R_DimSymbol) };
+    if dim.rtype() != INTSXP {
+        return Ok(None);
+    }
+    Ok(Some(Vec::<i32>::rnew(dim)?))
+}
+
+// The date/time rtype of a numeric vector, if any.
+fn time_class(object: SEXP) -> RResult<Option<&'static str>> {
+    if object.rtype() != REALSXP && object.rtype() != INTSXP {
+        return Ok(None);
+    }
+    for clazz in &[RTYPE_DATE, RTYPE_POSIXCT, RTYPE_DIFFTIME] {
+        if inherits(object, clazz)? {
+            return Ok(Some(clazz));
+        }
+    }
+    Ok(None)
+}
+
+// The class the decoder gives back to `object`, along with the other
+// attributes its encoding carries, when decoded from TSON or JSON.
+fn native_attributes(object: SEXP) -> RResult<(Vec<&'static str>, Vec<&'static str>)> {
+    let mut native = Vec::new();
+    let mut class: Vec<&'static str> = Vec::new();
+
+    if array_dim(object)?.is_some() {
+        native.push("dim");
+        native.push("dimnames");
+    }
+
+    match object.rtype() {
+        VECSXP => {
+            native.push("names");
+            if inherits(object, "data.frame")? {
+                native.push("row.names");
+                class.push("data.frame");
+            } else if inherits(object, "tsonmap")? {
+                class.push("tsonmap");
             }
-            Ok(Value::LSTU8(_vec))
         }
-        REALSXP => {
-            let object_ = Vec::<f64>::rnew(object)?;
-            if inherits(object, "scalar")? {
-                if object_.len() != 1 {
-                    Err(RError::unknown(format!("real : scalar bad length : {}", object_.len()).to_string()))
-                } else {
-                    Ok(Value::F64(object_[0]))
-                }
-            } else if inherits(object, "uint64")? {
-                let mut _vec = Vec::<u64>::with_capacity(object_.len());
-                for x in object_ {
-                    _vec.push(x as u64);
-                }
-                Ok(Value::LSTU64(_vec))
-            } else if inherits(object, "int64")? {
-                let mut _vec = Vec::<i64>::with_capacity(object_.len());
-                for x in object_ {
-                    _vec.push(x as i64);
-                }
-                Ok(Value::LSTI64(_vec))
-            } else {
-                Ok(Value::LSTF64(object_))
+        INTSXP if inherits(object, "factor")? => {
+            native.push("levels");
+            if inherits(object, "ordered")? {
+                class.push("ordered");
             }
+            class.push("factor");
         }
-        INTSXP => {
-            let object_ = Vec::<i32>::rnew(object)?;
-            if inherits(object, "scalar")? {
-                if object_.len() != 1 {
-                    Err(RError::unknown(format!("int : scalar bad length : {}", object_.len()).to_string()))
-                } else {
-                    Ok(Value::I32(object_[0]))
-                }
-            } else {
-                if inherits(object, "int8")? {
-                    let mut _vec = Vec::<i8>::with_capacity(object_.len());
-                    for x in object_ {
-                        _vec.push(x as i8);
-                    }
-                    Ok(Value::LSTI8(_vec))
-                } else if inherits(object, "int16")? {
-                    let mut _vec = Vec::<i16>::with_capacity(object_.len());
-                    for x in object_ {
-                        _vec.push(x as i16);
-                    }
-                    Ok(Value::LSTI16(_vec))
-                } else if inherits(object, "int64")? {
-                    let mut _vec = Vec::<i64>::with_capacity(object_.len());
-                    for x in object_ {
-                        _vec.push(x as i64);
-                    }
-                    Ok(Value::LSTI64(_vec))
-                } else if inherits(object, "uint8")? {
-                    let mut _vec = Vec::<u8>::with_capacity(object_.len());
-                    for x in object_ {
-                        _vec.push(x as u8);
-                    }
-                    Ok(Value::LSTU8(_vec))
-                } else if inherits(object, "uint16")? {
-                    let mut _vec = Vec::<u16>::with_capacity(object_.len());
-                    for x in object_ {
-                        _vec.push(x as u16);
-                    }
-                    Ok(Value::LSTU16(_vec))
-                } else if inherits(object, "uint64")? {
-                    let mut _vec = Vec::<u64>::with_capacity(object_.len());
-                    for x in object_ {
-                        _vec.push(x as u64);
-                    }
-                    Ok(Value::LSTU64(_vec))
-                } else if inherits(object, "uint32")? {
-                    let mut _vec = Vec::<u32>::with_capacity(object_.len());
-                    for x in object_ {
-                        _vec.push(x as u32);
-                    }
-                    Ok(Value::LSTU32(_vec))
-                } else {
-                    Ok(Value::LSTI32(object_))
+        _ => {}
+    }
+
+    match time_class(object)? {
+        Some(RTYPE_POSIXCT) => {
+            native.push(TZONE_KEY);
+            class.push("POSIXct");
+            class.push("POSIXt");
+        }
+        Some(RTYPE_DIFFTIME) => {
+            native.push(UNITS_KEY);
+            class.push("difftime");
+        }
+        Some(clazz) => class.push(clazz),
+        None => {
+            let restored: &[&'static str] = match object.rtype() {
+                INTSXP => &["scalar", "int8", "int16", "uint16"],
+                REALSXP => &["scalar", "int64", "uint64", "uint32", "float32", "integer64"],
+                STRSXP => &["scalar"],
+                _ => &[],
+            };
+            for clazz in restored {
+                if inherits(object, clazz)? {
+                    class.push(clazz);
+                    break;
                 }
             }
         }
-        LGLSXP => {
-            let object_ = Vec::<bool>::rnew(object)?;
-            if object_.len() != 1 {
-                Err(RError::unknown(format!("bool : bad length : {}", object_.len()).to_string()))
+    }
+
+    Ok((class, native))
+}
+
+// Attributes of `object` that its encoding does not carry by itself, read
+// without expanding compact row names. When there are any, the class is
+// always one of them, so that the decoder sets it exactly instead of the
+// classes it tags decoded values with.
+fn extra_attributes(object: SEXP) -> RResult<Vec<(String, SEXP)>> {
+    let (class, native) = native_attributes(object)?;
+    let mut attributes = Vec::new();
+    let mut native_class = None;
+    unsafe {
+        let mut attr = ATTRIB(object);
+        while attr != R_NilValue {
+            let name = match char_to_utf8(PRINTNAME(TAG(attr))) {
+                Some(name) => name,
+                None => return Err(RError::unknown("attributes : invalid UTF-8 name".to_string())),
+            };
+            let value = CAR(attr);
+            let is_native = if TAG(attr) == R_ClassSymbol {
+                value.rtype() == STRSXP && Vec::<String>::rnew(value)? == class
             } else {
-                Ok(Value::BOOL(object_[0]))
+                native.contains(&name.as_str())
+            };
+            if !is_native {
+                attributes.push((name, value));
+            } else if TAG(attr) == R_ClassSymbol {
+                native_class = Some((name, value));
             }
+            attr = CDR(attr);
         }
-        VECSXP => {
-            /* generic vectors */
-            // empty list
-            let rlist = RList::new(object)?;
+    }
+    if !attributes.is_empty() {
+        attributes.extend(native_class);
+    }
+    Ok(attributes)
+}
+
+// The expressions of `text`, parsed without being evaluated.
+fn parse(text: &str) -> RTsonResult<SEXP> {
+    unsafe {
+        let text = Rf_protect(utf8_strings(&[text])?);
+        let mut status = 0;
+        let exprs = R_ParseVector(text, -1, &mut status, R_NilValue);
+        Rf_unprotect(1);
+        if status != PARSE_OK {
+            return http_raise("language : cannot parse");
+        }
+        Ok(exprs)
+    }
+}
+
+const DEPARSE: &str = "function(x) paste(deparse(x, width.cutoff = 500L, backtick = TRUE, \
+                       control = c('keepInteger', 'keepNA', 'digits17')), collapse = '\\n')";
+
+// Text of a call or symbol that parses back into it. Its attributes are
+// left out, the preserve attributes mode writes them separately.
+fn deparse_language(object: SEXP) -> RTsonResult<String> {
+    let quote = symbol("quote")?;
+    let exprs = parse(DEPARSE)?;
+    unsafe {
+        Rf_protect(exprs);
+        let mut error = 0;
+        let fun = Rf_protect(R_tryEval(VECTOR_ELT(exprs, 0), R_BaseEnv, &mut error));
+        let call = Rf_protect(Rf_lang2(fun, Rf_lang2(quote, object)));
+        let text = Rf_protect(R_tryEval(call, R_BaseEnv, &mut error));
+        let result = if error != 0 || text.rtype() != STRSXP || Rf_xlength(text) != 1 {
+            http_raise("language : cannot deparse")
+        } else {
+            string_elt(text, 0)
+        };
+        Rf_unprotect(4);
+        result
+    }
+}
+
+// Name RTYPE_ENVIRONMENT gives an environment.
+fn environment_name(object: SEXP) -> RTsonResult<&'static str> {
+    unsafe {
+        if object == R_GlobalEnv {
+            Ok("global")
+        } else if object == R_BaseEnv {
+            Ok("base")
+        } else if object == R_EmptyEnv {
+            Ok("empty")
+        } else {
+            http_raise("environment : only the global, base and empty environments can be written")
+        }
+    }
+}
+
+fn environment_from_name(name: &str) -> Option<SEXP> {
+    unsafe {
+        match name {
+            "global" => Some(R_GlobalEnv),
+            "base" => Some(R_BaseEnv),
+            "empty" => Some(R_EmptyEnv),
+            _ => None,
+        }
+    }
+}
+
+// Labels of a factor as a character vector, keeping NA.
+fn factor_labels<T, F>(object: SEXP, f: F) -> RResult<T>
+    where F: FnOnce(SEXP) -> RResult<T> {
+    unsafe {
+        let labels = Rf_protect(Rf_asCharacterFactor(object));
+        let result = f(labels);
+        Rf_unprotect(1);
+        result
+    }
+}
+
+fn na_real() -> f64 {
+    f64::from_bits(NA_REAL_BITS)
+}
+
+// Same test as R_IsNA: a NaN whose low word is 1954, so NaN is not NA.
+fn is_na_real(x: f64) -> bool {
+    x.is_nan() && (x.to_bits() & 0xFFFF_FFFF) == 1954
+}
+
+fn real_to_f32(x: f64) -> f32 {
+    if is_na_real(x) { f32::from_bits(NA_FLOAT32_BITS) } else { x as f32 }
+}
+
+fn f32_to_real(x: f32) -> f64 {
+    if x.to_bits() == NA_FLOAT32_BITS { na_real() } else { x as f64 }
+}
+
+fn integer64_values(values: &[f64]) -> Vec<i64> {
+    values.iter().map(|x| x.to_bits() as i64).collect()
+}
+
+fn integer64_to_real(x: i64) -> f64 {
+    f64::from_bits(x as u64)
+}
+
+fn uint64_to_integer64(x: u64, index: usize, na_sentinel: bool) -> RTsonResult<i64> {
+    if na_sentinel && x == std::u64::MAX {
+        Ok(NA_INTEGER64)
+    } else if x > std::i64::MAX as u64 {
+        http_raise(format!("integer64 : value {} out of range at index {}", x, index))
+    } else {
+        Ok(x as i64)
+    }
+}
+
+// Largest integer magnitude a double holds exactly.
+const MAX_EXACT_REAL: i128 = 1 << 53;
 
-            let names: CharVec = RName::name(&rlist);
+// Converts wide integers, NA being `None`, to the smallest R type holding
+// every value exactly. Doubles keep `clazz` so that they are encoded back
+// with the same type.
+fn narrow_integers(values: &[Option<i128>], clazz: &str) -> RTsonResult<SEXP> {
+    // the first value outside [min, max] with its index, if any
+    let outside = |min: i128, max: i128| {
+        values.iter().enumerate().find_map(|(i, x)| match *x {
+            Some(x) if x < min || x > max => Some((i, x)),
+            _ => None,
+        })
+    };
 
-            if names.rsize() > 0 || inherits(object, "tsonmap")? {
-                let mut map = HashMap::with_capacity(names.rsize() as usize);
-                let mut index = 0;
+    // integer64 holds every i64 but its NA
+    if let Some((i, x)) = outside(NA_INTEGER64 as i128 + 1, std::i64::MAX as i128) {
+        return http_raise(format!("{} : value {} at index {} has no exact R type", clazz, x, i));
+    }
 
-                for x in rlist {
-                    map.insert(names.at(index)?, r_to_value(x)?);
-                    index = index + 1;
+    if outside(NA_INTEGER as i128 + 1, std::i32::MAX as i128).is_none() {
+        let mut object = IntVec::alloc(values.len());
+        unsafe {
+            for (i, x) in values.iter().enumerate() {
+                object.uset(i, x.map_or(NA_INTEGER, |x| x as i32));
+            }
+        }
+        Ok(object.intor()?)
+    } else if outside(-MAX_EXACT_REAL, MAX_EXACT_REAL).is_none() {
+        let mut object = NumVec::alloc(values.len());
+        unsafe {
+            for (i, x) in values.iter().enumerate() {
+                object.uset(i, x.map_or(na_real(), |x| x as f64));
+            }
+        }
+        Ok(set_class(object.intor()?, clazz)?)
+    } else {
+        let mut object = NumVec::alloc(values.len());
+        unsafe {
+            for (i, x) in values.iter().enumerate() {
+                object.uset(i, integer64_to_real(x.map_or(NA_INTEGER64, |x| x as i64)));
+            }
+        }
+        Ok(set_class(object.intor()?, "integer64")?)
+    }
+}
+
+fn na_error(clazz: &str, index: usize) -> RTsonError {
+    RTsonError::new(format!("{} : NA at index {}", clazz, index))
+}
+
+// Fails on the first NA of `values` when NA is an error.
+fn check_na<T, F>(options: &EncodeOptions, clazz: &str, values: &[T], is_na: F) -> RTsonResult<()>
+    where F: Fn(&T) -> bool {
+    if options.na == NaPolicy::Error {
+        if let Some(i) = values.iter().position(is_na) {
+            return Err(na_error(clazz, i));
+        }
+    }
+    Ok(())
+}
+
+// Fails when a vector of the `scalar` class does not hold a single value.
+fn check_scalar(clazz: &str, len: usize) -> RTsonResult<()> {
+    if len != 1 {
+        return http_raise(format!("{} : scalar bad length : {}", clazz, len));
+    }
+    Ok(())
+}
+
+// Values of one vector changed by a conversion.
+#[derive(Debug, Clone, PartialEq)]
+struct PrecisionLoss {
+    clazz: String,
+    target: String,
+    count: usize,
+    first_index: usize,
+}
+
+impl fmt::Display for PrecisionLoss {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} : {} value(s) not exact as {}, first at index {}",
+               self.clazz, self.count, self.target, self.first_index)
+    }
+}
+
+// Collects the precision lost while converting an object, reported once
+// the conversion is done as one R warning per vector. In strict mode any
+// loss is an error instead.
+struct Losses {
+    strict: bool,
+    losses: RefCell<Vec<PrecisionLoss>>,
+}
+
+impl Losses {
+    fn new(strict: bool) -> Losses {
+        Losses { strict, losses: RefCell::new(Vec::new()) }
+    }
+
+    // `lossy` tells, for each value of a vector, whether it is changed.
+    fn check<I>(&self, clazz: &str, target: &str, lossy: I) -> RTsonResult<()>
+        where I: Iterator<Item = bool> {
+        let mut count = 0;
+        let mut first_index = 0;
+        for (i, x) in lossy.enumerate() {
+            if x {
+                if count == 0 {
+                    first_index = i;
                 }
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return Ok(());
+        }
 
-                Ok(Value::MAP(map))
-            } else {
-                let mut list: Vec<Value> = Vec::with_capacity(rlist.rsize() as usize);
+        let loss = PrecisionLoss {
+            clazz: clazz.to_string(),
+            target: target.to_string(),
+            count,
+            first_index,
+        };
+        if self.strict {
+            return http_raise(loss.to_string());
+        }
+        self.losses.borrow_mut().push(loss);
+        Ok(())
+    }
+
+    fn clear(&self) {
+        self.losses.borrow_mut().clear();
+    }
+
+    // The messages of the losses collected, as a character vector or NULL
+    // when there are none, leaving none collected. The losses are warned
+    // about by the entry points, see warn. `object` is kept protected while
+    // the vector is allocated.
+    fn take(&self, object: SEXP) -> RTsonResult<SEXP> {
+        let messages: Vec<String> = self.losses.borrow_mut().drain(..).map(|loss| loss.to_string()).collect();
+        if messages.is_empty() {
+            return Ok(unsafe { R_NilValue });
+        }
+        unsafe { Rf_protect(object) };
+        let warnings = utf8_strings(&messages);
+        unsafe { Rf_unprotect(1) };
+        Ok(warnings?)
+    }
+}
+
+// Whether writing `x` as an integer of the exclusive range (lo, hi), the
+// bounds being the sentinels or out of range, changes it. NA is kept.
+fn real_cast_lossy(x: f64, lo: f64, hi: f64) -> bool {
+    !is_na_real(x) && (x.is_nan() || x <= lo || x >= hi || x.fract() != 0.0)
+}
+
+fn int_to_real_lossy(x: i128) -> bool {
+    (x as f64) as i128 != x
+}
 
-                for x in rlist {
-                    list.push(r_to_value(x)?);
+fn is_na_string(object: SEXP, index: usize) -> bool {
+    unsafe {
+        STRING_ELT(object, index as R_xlen_t) == R_NaString
+    }
+}
+
+fn string_nas(object: SEXP) -> Vec<usize> {
+    let len = unsafe { Rf_xlength(object) as usize };
+    (0..len).filter(|i| is_na_string(object, *i)).collect()
+}
+
+fn list_elt(object: SEXP, index: usize) -> SEXP {
+    unsafe { VECTOR_ELT(object, index as R_xlen_t) }
+}
+
+// A CHARSXP as UTF-8, translated from the encoding it declares. Strings
+// marked as bytes have no encoding to translate from and are kept as is.
+// None when the result is not valid UTF-8.
+fn char_to_utf8(charsxp: SEXP) -> Option<String> {
+    unsafe {
+        let bytes = if Rf_getCharCE(charsxp) == cetype_t::CE_BYTES {
+            CStr::from_ptr(R_CHAR(charsxp)).to_bytes()
+        } else {
+            CStr::from_ptr(Rf_translateCharUTF8(charsxp)).to_bytes()
+        };
+        std::str::from_utf8(bytes).ok().map(|x| x.to_string())
+    }
+}
+
+// Element `index` of a character vector as UTF-8, NA reads as "".
+fn string_elt(object: SEXP, index: usize) -> RTsonResult<String> {
+    let charsxp = unsafe { STRING_ELT(object, index as R_xlen_t) };
+    if charsxp == unsafe { R_NaString } {
+        return Ok(String::new());
+    }
+    match char_to_utf8(charsxp) {
+        Some(value) => Ok(value),
+        None => http_raise(format!("str : invalid UTF-8 at index {}", index)),
+    }
+}
+
+fn strings(object: SEXP) -> RTsonResult<Vec<String>> {
+    let len = unsafe { Rf_xlength(object) as usize };
+    (0..len).map(|i| string_elt(object, i)).collect()
+}
+
+fn mk_char_utf8(value: &str) -> SEXP {
+    unsafe {
+        Rf_mkCharLenCE(value.as_ptr() as *const std::os::raw::c_char, value.len() as i32, cetype_t::CE_UTF8)
+    }
+}
+
+fn mk_char_bytes(value: &[u8]) -> SEXP {
+    unsafe {
+        Rf_mkCharLenCE(value.as_ptr() as *const std::os::raw::c_char, value.len() as i32, cetype_t::CE_BYTES)
+    }
+}
+
+// A character vector of `values`, marked as UTF-8.
+fn utf8_strings<S: AsRef<str>>(values: &[S]) -> RResult<SEXP> {
+    let object = CharVec::alloc(values.len());
+    for (i, value) in values.iter().enumerate() {
+        unsafe {
+            SET_STRING_ELT(object.s(), i as R_xlen_t, mk_char_utf8(value.as_ref()));
+        }
+    }
+    object.intor()
+}
+
+// Keys of a named list of `len` elements, as (element index, key) pairs
+// in the order they are written, following the names policy. A name is
+// missing when it is "", NA or beyond the end of `names`.
+fn map_keys(names: &CharVec, len: usize, policy: NamesPolicy) -> RTsonResult<Vec<(usize, String)>> {
+    let nnames = names.rsize() as usize;
+    let mut given = Vec::with_capacity(len);
+    for i in 0..len {
+        if i < nnames && !is_na_string(names.s(), i) {
+            given.push(string_elt(names.s(), i)?);
+        } else {
+            given.push(String::new());
+        }
+    }
+
+    let mut keys = Vec::with_capacity(len);
+    let mut seen = HashSet::with_capacity(len);
+    // generated keys must not clash with names given later on
+    let taken: HashSet<String> = given.iter().filter(|x| !x.is_empty()).cloned().collect();
+    let unique = |base: String, seen: &HashSet<String>| {
+        let mut key = base.clone();
+        let mut n = 1;
+        while seen.contains(&key) || taken.contains(&key) {
+            key = format!("{}.{}", base, n);
+            n += 1;
+        }
+        key
+    };
+    match policy {
+        NamesPolicy::Error => {
+            for (i, name) in given.into_iter().enumerate() {
+                if name.is_empty() {
+                    return http_raise(format!("names : missing name at index {}", i));
+                }
+                if !seen.insert(name.clone()) {
+                    return http_raise(format!("names : duplicate name '{}' at index {}", name, i));
+                }
+                keys.push((i, name));
+            }
+        }
+        // elements with a missing name are never dropped, nor written under
+        // the same empty key
+        NamesPolicy::KeepFirst => {
+            for (i, name) in given.into_iter().enumerate() {
+                if name.is_empty() {
+                    let key = unique(format!("V{}", i + 1), &seen);
+                    seen.insert(key.clone());
+                    keys.push((i, key));
+                } else if seen.insert(name.clone()) {
+                    keys.push((i, name));
+                }
+            }
+        }
+        NamesPolicy::KeepLast => {
+            for (i, name) in given.into_iter().enumerate().rev() {
+                if name.is_empty() {
+                    let key = unique(format!("V{}", i + 1), &seen);
+                    seen.insert(key.clone());
+                    keys.push((i, key));
+                } else if seen.insert(name.clone()) {
+                    keys.push((i, name));
                 }
-                Ok(Value::LST(list))
             }
+            keys.reverse();
         }
-        STRSXP => {
-            let object_: Vec<String> = Vec::<String>::rnew(object)?;
-            if inherits(object, "scalar")? {
-                if object_.len() != 1 {
-                    Err(RError::unknown(format!("int : scalar bad length : {}", object_.len()).to_string()))
+        NamesPolicy::Rename => {
+            for (i, name) in given.into_iter().enumerate() {
+                let key = if !name.is_empty() && !seen.contains(&name) {
+                    name
+                } else if name.is_empty() {
+                    unique(format!("V{}", i + 1), &seen)
                 } else {
-                    Ok(Value::STR(object_[0].clone()))
+                    unique(name, &seen)
+                };
+                seen.insert(key.clone());
+                keys.push((i, key));
+            }
+        }
+    }
+    Ok(keys)
+}
+
+// The names policy for the columns of a data.frame, which are never
+// dropped: duplicate names are renamed instead of kept once.
+fn column_names_policy(policy: NamesPolicy) -> NamesPolicy {
+    match policy {
+        NamesPolicy::KeepFirst | NamesPolicy::KeepLast => NamesPolicy::Rename,
+        policy => policy,
+    }
+}
+
+// Converts R integers to a narrower or wider integer type, failing on the
+// first value that does not fit. NA is written as `na`, which is then
+// reserved and not accepted as a value.
+fn convert_ints<T>(values: &[i32], clazz: &str, na: Option<T>) -> RTsonResult<Vec<T>>
+    where T: TryFrom<i32> + PartialEq + Copy {
+    let mut _vec = Vec::<T>::with_capacity(values.len());
+    for (i, x) in values.iter().enumerate() {
+        if *x == NA_INTEGER {
+            match na {
+                Some(v) => _vec.push(v),
+                None => {
+                    return http_raise(format!("{} : NA not representable at index {}", clazz, i));
                 }
-            } else {
-                Ok(Value::LSTSTR(object_.into()))
             }
+            continue;
+        }
+        match convert_int(*x, clazz, i)? {
+            v if Some(v) == na => {
+                return http_raise(format!("{} : value {} at index {} is reserved as the NA sentinel, \
+                                           it is only written when NA is an error", clazz, x, i));
+            }
+            v => _vec.push(v),
         }
-        _ => Err(RError::unknown(format!("bad object type : {}", object.rtype()).to_string()))
+    }
+    Ok(_vec)
+}
+
+// Converts the R integer at index `i`, which is not NA, to the integer
+// type `clazz` names.
+fn convert_int<T>(x: i32, clazz: &str, i: usize) -> RTsonResult<T>
+    where T: TryFrom<i32> {
+    match T::try_from(x) {
+        Ok(v) => Ok(v),
+        Err(_) => http_raise(format!("{} : value {} out of range at index {}", clazz, x, i)),
+    }
+}
+
+// The NA sentinel of an integer type, which is then a reserved value. When
+// NA is an error no NA is written, so nothing is reserved.
+fn sentinel<T>(options: &EncodeOptions, na: T) -> Option<T> {
+    if options.na == NaPolicy::Error {
+        None
+    } else {
+        Some(na)
+    }
+}
+
+// Converts R doubles holding integers with `cast`, writing NA and NaN as `na`.
+fn convert_reals<T, F>(values: &[f64], na: T, cast: F) -> Vec<T>
+    where T: Copy, F: Fn(f64) -> T {
+    values.iter()
+        .map(|x| if x.is_nan() { na } else { cast(*x) })
+        .collect()
+}
+
+// Looks up an attribute without the expansion Rf_getAttrib applies to
+// compact row names, so nothing is allocated and the result is protected
+// by `object`.
+fn raw_attrib(object: SEXP, name: SEXP) -> Option<SEXP> {
+    unsafe {
+        let mut attr = ATTRIB(object);
+        while attr != R_NilValue {
+            if TAG(attr) == name {
+                return Some(CAR(attr));
+            }
+            attr = CDR(attr);
+        }
+    }
+    None
+}
+
+// Number of rows of a data.frame and its row names, None when they are
+// the automatic 1..nrow.
+fn data_frame_rows(object: SEXP) -> (usize, Option<SEXP>) {
+    let row_names = match raw_attrib(object, R_RowNamesSymbol) {
+        Some(v) => v,
+        None => return (0, None),
+    };
+    unsafe {
+        let len = Rf_xlength(row_names) as usize;
+        if row_names.rtype() == INTSXP {
+            let values = std::slice::from_raw_parts(INTEGER(row_names), len);
+            if len == 2 && values[0] == NA_INTEGER {
+                return ((values[1] as i64).abs() as usize, None);
+            }
+            if values.iter().enumerate().all(|(i, x)| *x as i64 == i as i64 + 1) {
+                return (len, None);
+            }
+        }
+        (len, Some(row_names))
+    }
+}
+
+// R names of the atomic vector types, as typeof gives them.
+const ATOMIC_TYPE_NAMES: &[(SEXPTYPE, &str)] = &[
+    (LGLSXP, "logical"),
+    (INTSXP, "integer"),
+    (REALSXP, "double"),
+    (CPLXSXP, "complex"),
+    (STRSXP, "character"),
+    (RAWSXP, "raw"),
+];
+
+fn atomic_type_name(rtype: SEXPTYPE) -> Option<&'static str> {
+    ATOMIC_TYPE_NAMES.iter().find(|x| x.0 == rtype).map(|x| x.1)
+}
+
+fn atomic_type_from_name(name: &str) -> Option<SEXPTYPE> {
+    ATOMIC_TYPE_NAMES.iter().find(|x| x.1 == name).map(|x| x.0)
+}
+
+fn real_slice(object: &SEXP) -> &[f64] {
+    unsafe {
+        let len = Rf_xlength(*object) as usize;
+        if len == 0 {
+            return &[];
+        }
+        std::slice::from_raw_parts(REAL(*object), len)
+    }
+}
+
+fn int_slice(object: &SEXP) -> &[i32] {
+    unsafe {
+        let len = Rf_xlength(*object) as usize;
+        if len == 0 {
+            return &[];
+        }
+        std::slice::from_raw_parts(INTEGER(*object), len)
+    }
+}
+
+fn logical_slice(object: &SEXP) -> &[i32] {
+    unsafe {
+        let len = Rf_xlength(*object) as usize;
+        if len == 0 {
+            return &[];
+        }
+        std::slice::from_raw_parts(LOGICAL(*object), len)
+    }
+}
+
+// Real and imaginary parts of a complex vector, interleaved.
+fn complex_slice(object: &SEXP) -> &[f64] {
+    unsafe {
+        let len = Rf_xlength(*object) as usize;
+        if len == 0 {
+            return &[];
+        }
+        std::slice::from_raw_parts(COMPLEX(*object) as *const f64, 2 * len)
+    }
+}
+
+// A complex is NA when either part is, as in R.
+fn first_na_complex(values: &[f64]) -> Option<usize> {
+    values.chunks(2).position(|x| is_na_real(x[0]) || is_na_real(x[1]))
+}
+
+fn logical_to_u8(x: i32) -> u8 {
+    if x == NA_LOGICAL {
+        LOGICAL_NA_U8
+    } else if x != 0 {
+        1
+    } else {
+        0
+    }
+}
+
+// How a vector is encoded, decided from its type and class. Every encoder
+// dispatches on this, so that they agree on what a vector is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Null,
+    Raw,
+    // Date, POSIXct or difftime over doubles or integers.
+    Time(&'static str),
+    Integer64,
+    // Doubles, with the class giving the wire type, if any.
+    Real(Option<&'static str>),
+    Factor,
+    // Integers, with the class giving the wire type, if any.
+    Int(Option<&'static str>),
+    Logical,
+    Complex,
+    // Strings, true for a scalar.
+    Str(bool),
+    DataFrame,
+    Map,
+    List,
+    // Calls and symbols, only in the preserve attributes mode.
+    Language,
+    Environment,
+}
+
+const REAL_CLASSES: &[&str] = &["scalar", "uint64", "int64", "uint32", "float32"];
+const INT_CLASSES: &[&str] = &["scalar", "int8", "int16", "int64", "uint8", "uint16", "uint64", "uint32"];
+
+fn has_names(object: SEXP) -> bool {
+    match raw_attrib(object, unsafe { R_NamesSymbol }) {
+        Some(names) => unsafe { Rf_xlength(names) > 0 },
+        None => false,
+    }
+}
+
+fn first_class(object: SEXP, classes: &[&'static str]) -> RResult<Option<&'static str>> {
+    for clazz in classes {
+        if inherits(object, clazz)? {
+            return Ok(Some(clazz));
+        }
+    }
+    Ok(None)
+}
+
+fn vector_kind(object: SEXP, options: &EncodeOptions) -> RResult<Kind> {
+    let kind = match object.rtype() {
+        NILSXP => Kind::Null,
+        RAWSXP => Kind::Raw,
+        REALSXP | INTSXP if time_class(object)?.is_some() => Kind::Time(time_class(object)?.unwrap()),
+        REALSXP if inherits(object, "integer64")? => Kind::Integer64,
+        REALSXP => match first_class(object, REAL_CLASSES)? {
+            None if options.float32 => Kind::Real(Some("float32")),
+            clazz => Kind::Real(clazz),
+        },
+        INTSXP if inherits(object, "factor")? => Kind::Factor,
+        INTSXP => Kind::Int(first_class(object, INT_CLASSES)?),
+        LGLSXP => Kind::Logical,
+        CPLXSXP => Kind::Complex,
+        STRSXP => Kind::Str(inherits(object, "scalar")?),
+        VECSXP if inherits(object, "data.frame")? => Kind::DataFrame,
+        VECSXP if has_names(object) || inherits(object, "tsonmap")? => Kind::Map,
+        VECSXP => Kind::List,
+        LANGSXP | SYMSXP if options.preserve_attributes => Kind::Language,
+        ENVSXP if options.preserve_attributes => Kind::Environment,
+        _ => return Err(RError::unknown(format!("bad object type : {}", object.rtype()))),
+    };
+    Ok(kind)
+}
+
+pub fn r_to_value(object: SEXP) -> RResult<Value> {
+    r_to_value_with(object, &EncodeOptions::default())
+}
+
+// The Value the TSON encoding of `object` decodes to, so that it follows
+// the same rules as to_tson.
+pub fn r_to_value_with(object: SEXP, options: &EncodeOptions) -> RResult<Value> {
+    // the bytes wait in an R vector while warning
+    let rbytes = to_tson_with(object, options)?;
+    Ok(decode(raw_bytes(&rbytes)).map_err(RTsonError::from)?)
+}
+
+// Gives a decoded scalar the `scalar` class when the options ask for it.
+fn tag_scalar(object: SEXP, options: &DecodeOptions) -> RResult<SEXP> {
+    if options.tag_scalars {
+        set_class(object, "scalar")
+    } else {
+        Ok(object)
+    }
+}
+
+// Gives a decoded map the `tsonmap` class when the options ask for it.
+fn tag_map(object: SEXP, options: &DecodeOptions) -> RResult<SEXP> {
+    if options.tag_maps {
+        set_class(object, "tsonmap")
+    } else {
+        Ok(object)
     }
 }
 
 pub fn value_to_r(value: &Value) -> RResult<SEXP> {
+    value_to_r_with(value, &DecodeOptions::default())
+}
+
+pub fn value_to_r_with(value: &Value, options: &DecodeOptions) -> RResult<SEXP> {
+    let (object, warnings) = {
+        let losses = Losses::new(options.strict);
+        let object = value_to_r_in(value, options, &losses)?;
+        (object, losses.take(object)?)
+    };
+    warn(warnings, object);
+    Ok(object)
+}
+
+fn value_to_r_in(value: &Value, options: &DecodeOptions, losses: &Losses) -> RResult<SEXP> {
     match *value {
         Value::NULL => ().intor(),
-        Value::STR(ref v) => v.intor(),
-        Value::I32(v) => v.intor(),
-        Value::F64(v) => v.intor(),
+        Value::STR(ref v) => tag_scalar(utf8_strings(&[v])?, options),
+        Value::I32(v) => tag_scalar(v.intor()?, options),
+        Value::F64(v) => tag_scalar(v.intor()?, options),
         Value::BOOL(v) => v.intor(),
         Value::LST(ref v) => {
             let mut lst = RList::alloc(v.len());
             let mut i = 0;
             for obj in v.iter() {
-                match value_to_r(obj) {
+                match value_to_r_in(obj, options, losses) {
                     Ok(robj) => {
                         lst.set(i, robj)?;
                         i += 1;
@@ -308,12 +1188,20 @@ pub fn value_to_r(value: &Value) -> RResult<SEXP> {
             lst.intor()
         }
         Value::MAP(ref v) => {
-            let mut names = CharVec::alloc(v.len());
+            // a HashMap has no stable order, always sort by key
+            let mut entries: Vec<(&String, &Value)> = v.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+
+            let names = CharVec::alloc(v.len());
             let mut values = RList::alloc(v.len());
+            let mut keys = Vec::with_capacity(v.len());
             let mut i = 0;
-            for (k, obj) in v.iter() {
-                names.set(i, k)?;
-                match value_to_r(obj) {
+            for (k, obj) in entries {
+                unsafe {
+                    SET_STRING_ELT(names.s(), i as R_xlen_t, mk_char_utf8(k));
+                }
+                keys.push(k.clone());
+                match value_to_r_in(obj, options, losses) {
                     Ok(robj) => {
                         values.set(i, robj)?;
                         i += 1;
@@ -324,23 +1212,314 @@ pub fn value_to_r(value: &Value) -> RResult<SEXP> {
                 }
             }
 
+            if rtype::is_rtype(&keys) {
+                return Ok(rtype::rtype_to_r(&keys, &values, true)?);
+            }
+
             unsafe {
                 Rf_setAttrib(values.s(), R_NamesSymbol, names.s());
             }
-            values.intor()
+            tag_map(values.intor()?, options)
         }
         Value::LSTU8(ref v) => v.intor(),
-        Value::LSTI8(ref v) => v.intor(),
-        Value::LSTU16(ref v) => v.intor(),
-        Value::LSTI16(ref v) => v.intor(),
-        Value::LSTU32(ref v) => v.intor(),
+        Value::LSTI8(ref v) => set_class(v.intor()?, "int8"),
+        Value::LSTU16(ref v) => set_class(v.intor()?, "uint16"),
+        Value::LSTI16(ref v) => set_class(v.intor()?, "int16"),
+        Value::LSTU32(ref v) if options.narrow_integers => {
+            let values: Vec<Option<i128>> = v.iter()
+                .map(|x| if options.na_sentinels && *x == std::u32::MAX { None } else { Some(*x as i128) })
+                .collect();
+            Ok(narrow_integers(&values, "uint32")?)
+        }
+        Value::LSTU32(ref v) => set_class(v.intor()?, "uint32"),
         Value::LSTI32(ref v) => v.intor(),
-        Value::LSTU64(ref v) => v.intor(),
-        Value::LSTI64(ref v) => v.intor(),
-        Value::LSTF32(ref v) => v.intor(),
+        Value::LSTU64(ref v) if options.narrow_integers => {
+            let values: Vec<Option<i128>> = v.iter()
+                .map(|x| if options.na_sentinels && *x == std::u64::MAX { None } else { Some(*x as i128) })
+                .collect();
+            Ok(narrow_integers(&values, "uint64")?)
+        }
+        Value::LSTI64(ref v) if options.narrow_integers => {
+            let values: Vec<Option<i128>> = v.iter()
+                .map(|x| if options.na_sentinels && *x == std::i64::MIN { None } else { Some(*x as i128) })
+                .collect();
+            Ok(narrow_integers(&values, "int64")?)
+        }
+        Value::LSTU64(ref v) if options.integer64 => {
+            let mut values = Vec::with_capacity(v.len());
+            for (i, x) in v.iter().enumerate() {
+                values.push(integer64_to_real(uint64_to_integer64(*x, i, options.na_sentinels)?));
+            }
+            set_class(values.intor()?, "integer64")
+        }
+        Value::LSTI64(ref v) if options.integer64 => {
+            let values: Vec<f64> = v.iter().map(|x| integer64_to_real(*x)).collect();
+            set_class(values.intor()?, "integer64")
+        }
+        Value::LSTU64(ref v) => {
+            losses.check("uint64", "double", v.iter()
+                .map(|x| !(options.na_sentinels && *x == std::u64::MAX) && int_to_real_lossy(*x as i128)))?;
+            set_class(v.intor()?, "uint64")
+        }
+        Value::LSTI64(ref v) => {
+            losses.check("int64", "double", v.iter()
+                .map(|x| !(options.na_sentinels && *x == std::i64::MIN) && int_to_real_lossy(*x as i128)))?;
+            set_class(v.intor()?, "int64")
+        }
+        Value::LSTF32(ref v) => {
+            let values: Vec<f64> = v.iter().map(|x| f32_to_real(*x)).collect();
+            set_class(values.intor()?, "float32")
+        }
         Value::LSTF64(ref v) => v.intor(),
-        Value::LSTSTR(ref v) => v.try_to_vec()
-            .map_err(|e| RError::unknown(e.to_string()))?.intor() ,
+        Value::LSTSTR(ref v) => utf8_strings(&v.try_to_vec()
+            .map_err(|e| RError::unknown(e.to_string()))?),
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutil::*;
+
+    fn keys(names: &str, policy: NamesPolicy) -> RTsonResult<Vec<(usize, String)>> {
+        let names = CharVec::rnew(r(names)).unwrap();
+        let len = names.rsize() as usize;
+        map_keys(&names, len, policy)
+    }
+
+    fn pairs(keys: &[(usize, &str)]) -> Vec<(usize, String)> {
+        keys.iter().map(|&(i, k)| (i, k.to_string())).collect()
+    }
+
+    #[test]
+    fn names_policies() {
+        let _r = session();
+        let names = "c('a', '', 'b', 'a', NA)";
+        assert_eq!(keys(names, NamesPolicy::KeepFirst).unwrap(),
+                   pairs(&[(0, "a"), (1, "V2"), (2, "b"), (4, "V5")]));
+        assert_eq!(keys(names, NamesPolicy::KeepLast).unwrap(),
+                   pairs(&[(1, "V2"), (2, "b"), (3, "a"), (4, "V5")]));
+        assert_eq!(keys("c('V2', '', NA)", NamesPolicy::KeepLast).unwrap(),
+                   pairs(&[(0, "V2"), (1, "V2.1"), (2, "V3")]));
+        assert_eq!(keys(names, NamesPolicy::Rename).unwrap(),
+                   pairs(&[(0, "a"), (1, "V2"), (2, "b"), (3, "a.1"), (4, "V5")]));
+        assert_eq!(keys(names, NamesPolicy::Error).unwrap_err().to_string(),
+                   "names : missing name at index 1");
+        assert_eq!(keys("c('a', 'b', 'a')", NamesPolicy::Error).unwrap_err().to_string(),
+                   "names : duplicate name 'a' at index 2");
+    }
+
+    #[test]
+    fn missing_names_kept_by_default() {
+        let _r = session();
+        let x = r("list(a = 1, 2, 3)");
+        let y = from_tson(to_tson(x).unwrap()).unwrap();
+        assert!(identical(y, r("structure(list(a = 1, V2 = 2, V3 = 3), class = 'tsonmap')")));
+        match r_to_value(x).unwrap() {
+            Value::MAP(ref map) => assert_eq!(map.len(), 3),
+            ref value => panic!("{:?}", value),
+        }
+        assert_eq!(to_json(x).unwrap(), r#"{"a":[1.0],"V2":[2.0],"V3":[3.0]}"#);
+    }
+
+    #[test]
+    fn data_frame_columns_kept() {
+        let _r = session();
+        let x = r("data.frame(a = 1, a = 2, 3, check.names = FALSE)");
+        unsafe { Rf_setAttrib(x, R_NamesSymbol, r("c('a', 'a', '')")) };
+        let y = from_tson(to_tson(x).unwrap()).unwrap();
+        assert!(identical(y, r("data.frame(a = 1, a.1 = 2, V3 = 3)")));
+        let y = from_json(&to_json(x).unwrap()).unwrap();
+        assert!(identical(y, r("data.frame(a = 1, a.1 = 2, V3 = 3)")));
+    }
+
+    fn first_char(code: &str) -> SEXP {
+        unsafe { STRING_ELT(r(code), 0) }
+    }
+
+    #[test]
+    fn latin1_to_utf8() {
+        let _r = session();
+        let latin1 = first_char("iconv('caf\\u00e9', 'UTF-8', 'latin1')");
+        assert!(unsafe { Rf_getCharCE(latin1) } == cetype_t::CE_LATIN1);
+        assert_eq!(char_to_utf8(latin1), Some("caf\u{e9}".to_string()));
+        let utf8 = first_char("enc2utf8('caf\\u00e9')");
+        assert_eq!(char_to_utf8(utf8), Some("caf\u{e9}".to_string()));
+    }
+
+    #[test]
+    fn bytes_to_utf8() {
+        let _r = session();
+        // bytes strings are not translated, so only valid UTF-8 is read
+        let valid = first_char("x <- rawToChar(as.raw(c(0x63, 0x61, 0x66, 0xc3, 0xa9))); Encoding(x) <- 'bytes'; x");
+        assert_eq!(char_to_utf8(valid), Some("caf\u{e9}".to_string()));
+        let invalid = first_char("x <- rawToChar(as.raw(c(0x63, 0x61, 0x66, 0xe9))); Encoding(x) <- 'bytes'; x");
+        assert_eq!(char_to_utf8(invalid), None);
+        assert_eq!(string_elt(r("x"), 0).unwrap_err().to_string(), "str : invalid UTF-8 at index 0");
+    }
+
+    #[test]
+    fn losses_taken_as_warnings() {
+        let _r = session();
+        let object = r("structure(c(1, 1.5, 2.5), class = 'int64')");
+        let ser = RSerializer::new();
+        let bytes = ser.encode(&object).unwrap();
+        let warnings = preserve(ser.take_warnings(bytes.s()).unwrap());
+        assert!(identical(warnings, r("'real : 2 value(s) not exact as int64, first at index 1'")));
+        assert_eq!(ser.take_warnings(bytes.s()).unwrap(), unsafe { R_NilValue });
+
+        let value = Value::LSTU64(vec![1, (1 << 53) + 1]);
+        let deser = RTsonDeserializer::new();
+        let x = preserve(deser.read_slice(&encode(&value).unwrap()).unwrap());
+        let warnings = preserve(deser.take_warnings(x).unwrap());
+        assert!(identical(warnings, r("'uint64 : 1 value(s) not exact as double, first at index 1'")));
+        assert_eq!(deser.take_warnings(x).unwrap(), unsafe { R_NilValue });
+
+        // the entry points warn once done, with the same result
+        assert_eq!(raw_bytes(&to_tson(object).unwrap()), raw_bytes(&bytes));
+        assert_eq!(to_json(object).unwrap(), "[1,1,2]");
+        match r_to_value(object).unwrap() {
+            Value::LSTI64(ref v) => assert_eq!(v, &vec![1, 1, 2]),
+            ref value => panic!("{:?}", value),
+        }
+        assert!(identical(from_tson(to_tson(x).unwrap()).unwrap(), x));
+        assert!(identical(value_to_r(&value).unwrap(), x));
+    }
+
+    // `value` decoded in the narrow mode, through value_to_r and from its
+    // TSON encoding, which must agree.
+    fn narrowed(value: Value, options: &DecodeOptions) -> RTsonResult<SEXP> {
+        let mut options = options.clone();
+        options.narrow_integers = true;
+        let bytes = encode(&value).unwrap();
+        let by_tson = RTsonDeserializer::with_options(options.clone()).read_slice(&bytes).map(preserve);
+        match value_to_r_with(&value, &options) {
+            Ok(x) => {
+                assert!(identical(preserve(x), by_tson.unwrap()), "{:?}", value);
+                Ok(x)
+            }
+            Err(e) => {
+                let msg = by_tson.unwrap_err().to_string();
+                assert!(e.to_string().contains(&msg), "{} != {}", e, msg);
+                Err(RTsonError::new(msg))
+            }
+        }
+    }
+
+    fn integer64_bits(x: SEXP) -> Vec<i64> {
+        assert!(inherits(x, "integer64").unwrap());
+        integer64_values(real_slice(&x))
+    }
+
+    // ids no double holds exactly keep their bits both ways
+    #[test]
+    fn integer64_above_2_53() {
+        let _r = session();
+        let ids = vec![(1i64 << 53) + 1, -(1 << 60) - 3, std::i64::MAX];
+        let bits: Vec<f64> = ids.iter().map(|x| f64::from_bits(*x as u64)).collect();
+        let x = preserve(set_class(bits.intor().unwrap(), "integer64").unwrap());
+        let bytes = to_tson(x).unwrap();
+        assert_eq!(raw_bytes(&bytes), &encode(&Value::LSTI64(ids.clone())).unwrap()[..]);
+
+        let options = DecodeOptions { integer64: true, ..DecodeOptions::default() };
+        let y = preserve(from_tson_with(bytes, &options).unwrap());
+        assert_eq!(integer64_bits(y), ids);
+        assert!(identical(x, y));
+        let y = preserve(value_to_r_with(&Value::LSTI64(ids.clone()), &options).unwrap());
+        assert_eq!(integer64_bits(y), ids);
+    }
+
+    #[test]
+    fn narrow_integers_smallest_type() {
+        let _r = session();
+        let options = DecodeOptions::default();
+        let x = narrowed(Value::LSTU32(vec![1, std::u32::MAX, 7]), &options).unwrap();
+        assert!(identical(x, r("c(1L, NA, 7L)")));
+        let x = narrowed(Value::LSTI64(vec![-5, std::i64::MIN, std::i32::MAX as i64]), &options).unwrap();
+        assert!(identical(x, r("c(-5L, NA, 2147483647L)")));
+        let x = narrowed(Value::LSTU64(vec![]), &options).unwrap();
+        assert!(identical(x, r("integer(0)")));
+
+        let x = narrowed(Value::LSTU32(vec![std::u32::MAX - 1]), &options).unwrap();
+        assert!(identical(x, r("structure(4294967294, class = 'uint32')")));
+        let x = narrowed(Value::LSTI64(vec![std::i32::MIN as i64, 1 << 53]), &options).unwrap();
+        assert!(identical(x, r("structure(c(-2147483648, 2^53), class = 'int64')")));
+
+        let x = narrowed(Value::LSTI64(vec![(1 << 53) + 1, std::i64::MIN]), &options).unwrap();
+        assert_eq!(integer64_bits(x), vec![(1 << 53) + 1, NA_INTEGER64]);
+        let x = narrowed(Value::LSTU64(vec![std::i64::MAX as u64]), &options).unwrap();
+        assert_eq!(integer64_bits(x), vec![std::i64::MAX]);
+    }
+
+    #[test]
+    fn narrow_integers_no_exact_type() {
+        let _r = session();
+        let mut options = DecodeOptions::default();
+        let e = narrowed(Value::LSTU64(vec![1, std::u64::MAX - 1]), &options).unwrap_err();
+        assert_eq!(e.to_string(), "uint64 : value 18446744073709551614 at index 1 has no exact R type");
+
+        // without sentinels i64::MIN is a value, which integer64 keeps as NA
+        options.na_sentinels = false;
+        let e = narrowed(Value::LSTI64(vec![0, std::i64::MIN]), &options).unwrap_err();
+        assert_eq!(e.to_string(), "int64 : value -9223372036854775808 at index 1 has no exact R type");
+        let x = narrowed(Value::LSTU32(vec![std::u32::MAX]), &options).unwrap();
+        assert!(identical(x, r("structure(4294967295, class = 'uint32')")));
+    }
+
+    #[test]
+    fn narrow_arms_first() {
+        let _r = session();
+        // the narrow mode comes before integer64 and the plain lists
+        let mut options = DecodeOptions::default();
+        options.integer64 = true;
+        for value in vec![Value::LSTU32(vec![3]), Value::LSTI64(vec![3]), Value::LSTU64(vec![3])] {
+            let x = narrowed(value, &options).unwrap();
+            assert!(identical(x, r("3L")));
+        }
+        let plain = value_to_r(&Value::LSTU32(vec![3])).unwrap();
+        assert!(identical(plain, r("structure(3, class = 'uint32')")));
+    }
+
+    // `code` through TSON and JSON in the preserve attributes mode.
+    fn preserved(code: &str) {
+        let x = r(code);
+        let mut options = EncodeOptions::default();
+        options.preserve_attributes = true;
+        let y = from_tson(to_tson_with(x, &options).unwrap()).unwrap();
+        assert!(identical(x, y), "{} does not round trip through TSON", code);
+        let json = to_json_with(x, &options).unwrap();
+        let y = from_json(&json).unwrap();
+        assert!(identical(x, y), "{} does not round trip through {}", code, json);
+    }
+
+    #[test]
+    fn preserve_attributes() {
+        let _r = session();
+        preserved("c(a = 1, b = 2)");
+        preserved("c(a = 1L, b = NA)");
+        preserved("c(a = TRUE, b = FALSE)");
+        preserved("structure(1:3, units = 'cm', class = c('bar', 'foo'))");
+        preserved("structure(list(a = 1, b = list(c = 'x', d = 2:3)), class = 'foo')");
+        preserved("structure(list(a = 1), note = list(b = 'x'))");
+        preserved("structure(factor(c('a', 'b')), note = 'x')");
+        preserved("quote(f(x, `a b`, 1L, 0.1))");
+        preserved("y ~ x + log(z)");
+    }
+
+    #[test]
+    fn preserve_model_summary() {
+        let _r = session();
+        preserved("x <- 1:10; y <- c(2.1, 3.9, 6.2, 7.8, 10.1, 12.2, 13.8, 16.1, 18, 19.9); summary(lm(y ~ x))");
+    }
+
+    #[test]
+    fn preserve_environments() {
+        let _r = session();
+        let mut options = EncodeOptions::default();
+        options.preserve_attributes = true;
+        let e = to_tson_with(r("local(y ~ x)"), &options).unwrap_err();
+        assert!(e.to_string().contains("only the global, base and empty environments"), "{}", e);
+        assert!(to_tson(r("y ~ x")).unwrap_err().to_string().contains("bad object type"));
+    }
+}
@@ -0,0 +1,168 @@
+// How missing values (NA) are written.
+//
+// With `Sentinel`, integer NAs use the smallest signed (or largest unsigned)
+// value of the target type, double NAs keep R's NA_real_ bit pattern, and
+// character NAs are carried as a list of NA positions next to the strings.
+// The decoder maps these back to NA, see `DecodeOptions::na_sentinels`.
+// JSON has no sentinels, so NA is written as null there.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NaPolicy {
+    Sentinel,
+    // Same as Sentinel, except that a complex NA is written as null in
+    // JSON instead of a pair of nulls.
+    Null,
+    // Any NA is an error. No value is then reserved as a sentinel, so int8
+    // -128 and the like are written as values.
+    Error,
+}
+
+// How named lists with duplicate or missing ("" or NA) names are written
+// as maps. The columns of a data.frame are never dropped: KeepFirst and
+// KeepLast rename their duplicate names as Rename does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NamesPolicy {
+    // Any duplicate or missing name is an error.
+    Error,
+    // Only the first element with a given name is written. Elements with a
+    // missing name are all written, named as with Rename.
+    KeepFirst,
+    // Only the last element with a given name is written. Elements with a
+    // missing name are all written, named as with Rename.
+    KeepLast,
+    // Missing names become `V<position>` and duplicates get a `.1`, `.2`,
+    // ... suffix, as `make.unique` does, so that every element is written.
+    Rename,
+}
+
+#[derive(Debug, Clone)]
+pub struct EncodeOptions {
+    pub na: NaPolicy,
+    // Writes factors as their labels, a character vector, instead of
+    // codes and levels.
+    pub expand_factors: bool,
+    // Wraps objects whose attributes would otherwise be lost with a map
+    // of those attributes, so that the decoder restores them exactly.
+    pub preserve_attributes: bool,
+    pub names: NamesPolicy,
+    // Writes every double vector without a more specific class as a
+    // float32 list, as the `float32` class does for a single vector.
+    pub float32: bool,
+    // Fails on any value changed by a conversion, such as a fractional
+    // double written as an integer, instead of warning.
+    pub strict: bool,
+    // TSON strings end with a NUL byte, so a string containing one is an
+    // error unless this replaces it with the six characters `\u0000`. The
+    // substitution is one way: the decoders read them back as written.
+    pub escape_nul: bool,
+}
+
+impl EncodeOptions {
+    pub fn new() -> EncodeOptions {
+        EncodeOptions::default()
+    }
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            na: NaPolicy::Sentinel,
+            expand_factors: false,
+            preserve_attributes: false,
+            names: NamesPolicy::KeepLast,
+            float32: false,
+            strict: false,
+            escape_nul: false,
+        }
+    }
+}
+
+// How TSON strings that are not valid UTF-8 are decoded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Utf8Policy {
+    // Invalid UTF-8 is an error.
+    Strict,
+    // Invalid sequences are replaced with U+FFFD.
+    Lossy,
+    // Invalid strings are kept as is, marked with the "bytes" encoding.
+    Bytes,
+}
+
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    // Gives decoded string, integer and double scalars the `scalar` class,
+    // so that they are encoded back as scalars.
+    pub tag_scalars: bool,
+    // Gives decoded maps the `tsonmap` class, so that a map stays a map,
+    // even with no keys, when encoded back.
+    pub tag_maps: bool,
+    // Orders the elements of decoded maps, JSON and TSON alike, by key
+    // instead of the document order.
+    pub sort_keys: bool,
+    // Decodes int64 and uint64 lists into bit64 `integer64` vectors, which
+    // keep every value exactly, instead of doubles.
+    pub integer64: bool,
+    // Decodes uint32, int64 and uint64 lists into the smallest R type that
+    // holds every value exactly: integer, then double, then integer64. A
+    // list no R type holds exactly is an error.
+    pub narrow_integers: bool,
+    // Fails on any value changed by a conversion, such as a uint64 above
+    // 2^53 decoded as a double, instead of warning.
+    pub strict: bool,
+    // Reads the NA sentinels of integer lists, such as -128 in an int8
+    // list, as NA. Turned off, they are read as values, as TSON written
+    // by producers that have no NA needs.
+    pub na_sentinels: bool,
+    pub invalid_utf8: Utf8Policy,
+    pub limits: DecodeLimits,
+}
+
+impl DecodeOptions {
+    pub fn new() -> DecodeOptions {
+        DecodeOptions::default()
+    }
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            tag_scalars: true,
+            tag_maps: true,
+            sort_keys: false,
+            integer64: false,
+            narrow_integers: false,
+            strict: false,
+            na_sentinels: true,
+            invalid_utf8: Utf8Policy::Strict,
+            limits: DecodeLimits::default(),
+        }
+    }
+}
+
+// Bounds on what a TSON document may claim, checked before anything is
+// allocated, so that untrusted input fails with an error instead of
+// exhausting memory or the stack. None means no bound. A Reader has no
+// known size, lists read from it are then allocated as their elements are
+// read rather than from the length they claim.
+#[derive(Debug, Clone)]
+pub struct DecodeLimits {
+    // Size of the whole document. Lengths are also always bounded by the
+    // input size when it is known.
+    pub max_bytes: Option<usize>,
+    // Total number of list, map and vector elements.
+    pub max_elements: Option<usize>,
+    // Nesting of lists and maps.
+    pub max_depth: Option<usize>,
+    // Length of a single string in bytes.
+    pub max_string_length: Option<usize>,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_bytes: None,
+            max_elements: None,
+            max_depth: Some(1000),
+            max_string_length: None,
+        }
+    }
+}
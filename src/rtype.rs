@@ -0,0 +1,610 @@
+use super::*;
+
+// Rebuilds R objects from the reserved maps carrying RTYPE_KEY, shared by
+// the TSON and JSON decoders. `keys` are the map keys in the order of the
+// elements of `values`.
+
+pub fn is_rtype(keys: &[String]) -> bool {
+    keys.iter().any(|k| k == RTYPE_KEY)
+}
+
+// `simplify` is set by the JSON decoder, see `simplify_column`.
+pub fn rtype_to_r(keys: &[String], values: &RList, simplify: bool) -> RTsonResult<SEXP> {
+    let rtype = rtype_name(keys, values)?;
+
+    if rtype == RTYPE_LOGICAL {
+        logical_from_rtype(keys, values)
+    } else if rtype == RTYPE_CHARACTER {
+        character_from_rtype(keys, values)
+    } else if rtype == RTYPE_DATA_FRAME {
+        data_frame_from_rtype(keys, values, simplify)
+    } else if rtype == RTYPE_FACTOR {
+        factor_from_rtype(keys, values, simplify)
+    } else if rtype == RTYPE_COMPLEX {
+        complex_from_rtype(keys, values)
+    } else if rtype == RTYPE_ATTRIBUTES {
+        attributes_from_rtype(keys, values, simplify)
+    } else if rtype == RTYPE_LANGUAGE {
+        language_from_rtype(keys, values, simplify)
+    } else if rtype == RTYPE_ENVIRONMENT {
+        environment_from_rtype(keys, values, simplify)
+    } else if rtype == RTYPE_ARRAY {
+        array_from_rtype(keys, values, simplify)
+    } else if rtype == RTYPE_DATE || rtype == RTYPE_POSIXCT || rtype == RTYPE_DIFFTIME {
+        time_from_rtype(&rtype, keys, values, simplify)
+    } else {
+        Err(RTsonError::new(format!("wrong format : unknown rtype {}", rtype)))
+    }
+}
+
+fn rtype_entry_opt(keys: &[String], values: &RList, key: &str) -> Option<SEXP> {
+    keys.iter()
+        .position(|k| k == key)
+        .map(|i| unsafe { VECTOR_ELT(values.s(), i as R_xlen_t) })
+}
+
+fn rtype_entry(keys: &[String], values: &RList, key: &str) -> RTsonResult<SEXP> {
+    match rtype_entry_opt(keys, values, key) {
+        Some(v) => Ok(v),
+        None => Err(RTsonError::new(format!("wrong format : missing {}", key))),
+    }
+}
+
+fn rtype_name(keys: &[String], values: &RList) -> RTsonResult<String> {
+    let rtype = rtype_entry(keys, values, RTYPE_KEY)?;
+    unsafe {
+        if rtype.rtype() != STRSXP || Rf_xlength(rtype) != 1 {
+            return Err(RTsonError::new("wrong format : bad rtype"));
+        }
+        let name = CStr::from_ptr(R_CHAR(STRING_ELT(rtype, 0)));
+        Ok(name.to_string_lossy().into_owned())
+    }
+}
+
+fn logical_from_rtype(keys: &[String], values: &RList) -> RTsonResult<SEXP> {
+    let data = rtype_entry(keys, values, RTYPE_DATA_KEY)?;
+    if data.rtype() != RAWSXP {
+        return Err(RTsonError::new("wrong format : logical"));
+    }
+    unsafe {
+        let len = Rf_xlength(data) as usize;
+        let lgl = BoolVec::alloc(len);
+        let src = RAW(data);
+        let dst = LOGICAL(lgl.s());
+        for i in 0..len {
+            *dst.add(i) = match *src.add(i) {
+                0 => 0,
+                LOGICAL_NA_U8 => NA_LOGICAL,
+                _ => 1,
+            };
+        }
+        Ok(lgl.intor()?)
+    }
+}
+
+fn character_from_rtype(keys: &[String], values: &RList) -> RTsonResult<SEXP> {
+    let data = rtype_entry(keys, values, RTYPE_DATA_KEY)?;
+    let nas = rtype_entry(keys, values, RTYPE_NA_KEY)?;
+    if data.rtype() != STRSXP || nas.rtype() != INTSXP {
+        return Err(RTsonError::new("wrong format : character"));
+    }
+    unsafe {
+        let len = Rf_xlength(data);
+        let na_ptr = INTEGER(nas);
+        for i in 0..Rf_xlength(nas) {
+            let index = *na_ptr.offset(i as isize) as R_xlen_t;
+            if index < 0 || index >= len {
+                return Err(RTsonError::new("wrong format : character na index"));
+            }
+            SET_STRING_ELT(data, index, R_NaString);
+        }
+    }
+    Ok(data)
+}
+
+fn data_frame_from_rtype(keys: &[String], values: &RList, simplify: bool) -> RTsonResult<SEXP> {
+    let nrow = rtype_entry(keys, values, DF_NROW_KEY)?;
+    let columns = rtype_entry(keys, values, DF_COLUMNS_KEY)?;
+    if columns.rtype() != VECSXP {
+        return Err(RTsonError::new("wrong format : data.frame columns"));
+    }
+
+    unsafe {
+        let nrow = Rf_asInteger(nrow);
+        if nrow == NA_INTEGER || nrow < 0 {
+            return Err(RTsonError::new("wrong format : data.frame nrow"));
+        }
+
+        let types = rtype_entry_opt(keys, values, DF_TYPES_KEY);
+        for i in 0..Rf_xlength(columns) {
+            let mut column = VECTOR_ELT(columns, i);
+            if simplify {
+                column = simplify_column(column)?;
+                if column.rtype() == VECSXP && Rf_xlength(column) == 0 {
+                    if let Some(target) = types.and_then(|types| column_type(types, columns, i)) {
+                        column = Rf_allocVector(target, 0);
+                    }
+                }
+            }
+            if Rf_xlength(column) != nrow as R_xlen_t {
+                return Err(RTsonError::new("wrong format : data.frame column length"));
+            }
+            SET_VECTOR_ELT(columns, i, column);
+        }
+
+        match rtype_entry_opt(keys, values, DF_ROW_NAMES_KEY) {
+            Some(mut row_names) => {
+                if simplify {
+                    row_names = simplify_column(row_names)?;
+                }
+                match row_names.rtype() {
+                    INTSXP | STRSXP if Rf_xlength(row_names) == nrow as R_xlen_t => {}
+                    _ => return Err(RTsonError::new("wrong format : data.frame row.names")),
+                }
+                Rf_setAttrib(columns, R_RowNamesSymbol, row_names);
+            }
+            None => {
+                // compact form of the automatic row names 1..nrow
+                let mut row_names = IntVec::alloc(2);
+                row_names.uset(0, NA_INTEGER);
+                row_names.uset(1, -nrow);
+                Rf_setAttrib(columns, R_RowNamesSymbol, row_names.s());
+            }
+        }
+    }
+
+    Ok(set_class(columns, "data.frame")?)
+}
+
+// The type DF_TYPES_KEY gives column `index` of `columns`, if any.
+fn column_type(types: SEXP, columns: SEXP, index: R_xlen_t) -> Option<SEXPTYPE> {
+    unsafe {
+        let names = Rf_getAttrib(columns, R_NamesSymbol);
+        let type_names = Rf_getAttrib(types, R_NamesSymbol);
+        if types.rtype() != VECSXP || names.rtype() != STRSXP || type_names.rtype() != STRSXP {
+            return None;
+        }
+        let name = CStr::from_ptr(R_CHAR(STRING_ELT(names, index)));
+        for i in 0..Rf_xlength(types) {
+            if CStr::from_ptr(R_CHAR(STRING_ELT(type_names, i))) != name {
+                continue;
+            }
+            let rtype = VECTOR_ELT(types, i);
+            if rtype.rtype() != STRSXP || Rf_xlength(rtype) != 1 {
+                return None;
+            }
+            let rtype = CStr::from_ptr(R_CHAR(STRING_ELT(rtype, 0)));
+            return atomic_type_from_name(&rtype.to_string_lossy());
+        }
+    }
+    None
+}
+
+fn factor_from_rtype(keys: &[String], values: &RList, simplify: bool) -> RTsonResult<SEXP> {
+    let mut data = rtype_entry(keys, values, RTYPE_DATA_KEY)?;
+    let mut levels = rtype_entry(keys, values, FACTOR_LEVELS_KEY)?;
+    let ordered = rtype_entry(keys, values, FACTOR_ORDERED_KEY)?;
+    if simplify {
+        data = simplify_as(data, INTSXP)?;
+        if data.rtype() == REALSXP {
+            data = codes_from_reals(data)?;
+        }
+        levels = simplify_as(levels, STRSXP)?;
+    }
+    if data.rtype() != INTSXP || levels.rtype() != STRSXP {
+        return Err(RTsonError::new("wrong format : factor"));
+    }
+
+    unsafe {
+        let nlevels = Rf_xlength(levels) as i32;
+        let codes = INTEGER(data);
+        for i in 0..Rf_xlength(data) {
+            let code = *codes.offset(i as isize);
+            if code != NA_INTEGER && (code < 1 || code > nlevels) {
+                return Err(RTsonError::new("wrong format : factor code"));
+            }
+        }
+
+        Rf_setAttrib(data, R_LevelsSymbol, levels);
+        if Rf_asLogical(ordered) == 1 {
+            Ok(set_classes(data, &["ordered", "factor"])?)
+        } else {
+            Ok(set_class(data, "factor")?)
+        }
+    }
+}
+
+// Factor codes decoded from JSON as doubles, as an NA code written as the
+// integer sentinel reads back.
+fn codes_from_reals(data: SEXP) -> RTsonResult<SEXP> {
+    let values = Vec::<f64>::rnew(data)?;
+    let mut codes = IntVec::alloc(values.len());
+    for (i, x) in values.iter().enumerate() {
+        let code = if x.is_nan() {
+            NA_INTEGER
+        } else if x.fract() == 0.0 && *x >= NA_INTEGER as f64 && *x <= std::i32::MAX as f64 {
+            // NA_INTEGER itself maps to NA
+            *x as i32
+        } else {
+            return Err(RTsonError::new("wrong format : factor code"));
+        };
+        unsafe {
+            codes.uset(i, code);
+        }
+    }
+    Ok(codes.intor()?)
+}
+
+fn time_from_rtype(rtype: &str, keys: &[String], values: &RList, simplify: bool) -> RTsonResult<SEXP> {
+    let mut data = rtype_entry(keys, values, RTYPE_DATA_KEY)?;
+    if simplify {
+        data = simplify_as(data, REALSXP)?;
+    }
+    if data.rtype() != REALSXP && data.rtype() != INTSXP {
+        return Err(RTsonError::new(format!("wrong format : {}", rtype)));
+    }
+
+    if rtype == RTYPE_POSIXCT {
+        if let Some(mut tzone) = rtype_entry_opt(keys, values, TZONE_KEY) {
+            if simplify {
+                tzone = simplify_as(tzone, STRSXP)?;
+            }
+            unsafe {
+                Rf_setAttrib(data, symbol(TZONE_KEY)?, tzone);
+            }
+        }
+        Ok(set_classes(data, &["POSIXct", "POSIXt"])?)
+    } else if rtype == RTYPE_DIFFTIME {
+        let mut units = rtype_entry(keys, values, UNITS_KEY)?;
+        if simplify {
+            units = simplify_as(units, STRSXP)?;
+        }
+        if units.rtype() != STRSXP {
+            return Err(RTsonError::new(format!("wrong format : {}", rtype)));
+        }
+        unsafe {
+            Rf_setAttrib(data, symbol(UNITS_KEY)?, units);
+        }
+        Ok(set_class(data, "difftime")?)
+    } else {
+        Ok(set_class(data, "Date")?)
+    }
+}
+
+fn array_from_rtype(keys: &[String], values: &RList, simplify: bool) -> RTsonResult<SEXP> {
+    let mut data = rtype_entry(keys, values, RTYPE_DATA_KEY)?;
+    let mut dim = rtype_entry(keys, values, DIM_KEY)?;
+    let dimnames = rtype_entry_opt(keys, values, DIMNAMES_KEY);
+    if simplify {
+        data = simplify_column(data)?;
+        dim = simplify_column(dim)?;
+    }
+    match data.rtype() {
+        LGLSXP | INTSXP | REALSXP | CPLXSXP | STRSXP => {}
+        _ => return Err(RTsonError::new("wrong format : array data")),
+    }
+    if dim.rtype() != INTSXP {
+        return Err(RTsonError::new("wrong format : array dim"));
+    }
+
+    // R raises an error on inconsistent dim or dimnames, check them first
+    let dims = Vec::<i32>::rnew(dim)?;
+    let mut len: i64 = 1;
+    for d in dims.iter() {
+        if *d < 0 || *d == NA_INTEGER {
+            return Err(RTsonError::new("wrong format : array dim"));
+        }
+        len *= *d as i64;
+    }
+
+    unsafe {
+        if len != Rf_xlength(data) as i64 {
+            return Err(RTsonError::new("wrong format : array dim"));
+        }
+        Rf_setAttrib(data, R_DimSymbol, dim);
+
+        if let Some(dimnames) = dimnames {
+            if dimnames.rtype() != VECSXP || Rf_xlength(dimnames) as usize != dims.len() {
+                return Err(RTsonError::new("wrong format : array dimnames"));
+            }
+            for (i, d) in dims.iter().enumerate() {
+                let mut names = VECTOR_ELT(dimnames, i as R_xlen_t);
+                if simplify {
+                    names = simplify_as(names, STRSXP)?;
+                    SET_VECTOR_ELT(dimnames, i as R_xlen_t, names);
+                }
+                if names.rtype() != NILSXP && (names.rtype() != STRSXP || Rf_xlength(names) != *d as R_xlen_t) {
+                    return Err(RTsonError::new("wrong format : array dimnames"));
+                }
+            }
+            // named dimnames decode as a map, drop its tsonmap class
+            Rf_setAttrib(dimnames, R_ClassSymbol, R_NilValue);
+            Rf_setAttrib(data, R_DimNamesSymbol, dimnames);
+        }
+    }
+
+    Ok(data)
+}
+
+fn complex_from_rtype(keys: &[String], values: &RList) -> RTsonResult<SEXP> {
+    let data = rtype_entry(keys, values, RTYPE_DATA_KEY)?;
+
+    unsafe {
+        let data_len = Rf_xlength(data) as usize;
+        match data.rtype() {
+            REALSXP if data_len % 2 == 0 => {
+                let len = data_len / 2;
+                let cplx = Rf_allocVector(CPLXSXP, len as R_xlen_t);
+                if len > 0 {
+                    std::ptr::copy_nonoverlapping(REAL(data), COMPLEX(cplx) as *mut f64, data_len);
+                }
+                Ok(cplx)
+            }
+            VECSXP => {
+                // JSON, a list of {re, im} maps or NULL for NA
+                let cplx = Rf_allocVector(CPLXSXP, data_len as R_xlen_t);
+                let dst = COMPLEX(cplx) as *mut f64;
+                for i in 0..data_len {
+                    let x = VECTOR_ELT(data, i as R_xlen_t);
+                    let (re, im) = if x.rtype() == NILSXP {
+                        (na_real(), na_real())
+                    } else {
+                        (complex_part(x, COMPLEX_RE_KEY)?, complex_part(x, COMPLEX_IM_KEY)?)
+                    };
+                    *dst.add(2 * i) = re;
+                    *dst.add(2 * i + 1) = im;
+                }
+                Ok(cplx)
+            }
+            _ => Err(RTsonError::new("wrong format : complex")),
+        }
+    }
+}
+
+// The `key` element of a decoded {re, im} map. JSON has no NaN, so a part
+// that is NA or not finite is written as null and reads as NA.
+fn complex_part(pair: SEXP, key: &str) -> RTsonResult<f64> {
+    unsafe {
+        let names = Rf_getAttrib(pair, R_NamesSymbol);
+        if pair.rtype() == VECSXP && names.rtype() == STRSXP {
+            for i in 0..Rf_xlength(names) {
+                let name = CStr::from_ptr(R_CHAR(STRING_ELT(names, i)));
+                let value = VECTOR_ELT(pair, i);
+                if name.to_bytes() != key.as_bytes() {
+                    continue;
+                }
+                match value.rtype() {
+                    NILSXP => return Ok(na_real()),
+                    REALSXP | INTSXP if Rf_xlength(value) == 1 => return Ok(Rf_asReal(value)),
+                    _ => {}
+                }
+            }
+        }
+    }
+    Err(RTsonError::new(format!("wrong format : complex {}", key)))
+}
+
+fn attributes_from_rtype(keys: &[String], values: &RList, simplify: bool) -> RTsonResult<SEXP> {
+    let mut data = rtype_entry(keys, values, RTYPE_DATA_KEY)?;
+    let attributes = rtype_entry(keys, values, ATTRIBUTES_KEY)?;
+    if attributes.rtype() != VECSXP {
+        return Err(RTsonError::new("wrong format : attributes"));
+    }
+
+    unsafe {
+        let len = Rf_xlength(attributes);
+        if len == 0 {
+            return Ok(data);
+        }
+        let names = Rf_getAttrib(attributes, R_NamesSymbol);
+        match data.rtype() {
+            NILSXP | SYMSXP | ENVSXP => return Err(RTsonError::new("wrong format : attributes")),
+            _ if names.rtype() != STRSXP => return Err(RTsonError::new("wrong format : attributes")),
+            _ => {}
+        }
+
+        if simplify {
+            data = simplify_nested(data)?;
+        }
+        Rf_protect(data);
+        untag_maps(data);
+        // the class is one of the attributes, drop the one data was tagged with
+        Rf_setAttrib(data, R_ClassSymbol, R_NilValue);
+        let set = (0..len).map(|i| {
+            let mut value = VECTOR_ELT(attributes, i);
+            if simplify {
+                value = simplify_nested(value)?;
+            }
+            untag_maps(value);
+            Rf_setAttrib(data, Rf_installChar(STRING_ELT(names, i)), value);
+            Ok(())
+        }).collect::<RTsonResult<()>>();
+        Rf_unprotect(1);
+        set?;
+    }
+
+    Ok(data)
+}
+
+// The single string data of an rtype.
+fn rtype_text(keys: &[String], values: &RList, rtype: &str, simplify: bool) -> RTsonResult<String> {
+    let mut data = rtype_entry(keys, values, RTYPE_DATA_KEY)?;
+    if simplify {
+        data = simplify_as(data, STRSXP)?;
+    }
+    if data.rtype() != STRSXP || unsafe { Rf_xlength(data) } != 1 {
+        return Err(RTsonError::new(format!("wrong format : {}", rtype)));
+    }
+    string_elt(data, 0)
+}
+
+fn language_from_rtype(keys: &[String], values: &RList, simplify: bool) -> RTsonResult<SEXP> {
+    let exprs = parse(&rtype_text(keys, values, RTYPE_LANGUAGE, simplify)?)?;
+    unsafe {
+        if Rf_xlength(exprs) == 1 {
+            let object = VECTOR_ELT(exprs, 0);
+            if object.rtype() == LANGSXP || object.rtype() == SYMSXP {
+                return Ok(object);
+            }
+        }
+    }
+    Err(RTsonError::new("wrong format : language"))
+}
+
+fn environment_from_rtype(keys: &[String], values: &RList, simplify: bool) -> RTsonResult<SEXP> {
+    match environment_from_name(&rtype_text(keys, values, RTYPE_ENVIRONMENT, simplify)?) {
+        Some(env) => Ok(env),
+        None => Err(RTsonError::new("wrong format : environment")),
+    }
+}
+
+// Whether `object` is a list as the decoders give them, with no attribute
+// but names and the tsonmap class.
+fn is_plain_list(object: SEXP) -> bool {
+    if object.rtype() != VECSXP {
+        return false;
+    }
+    unsafe {
+        let mut attr = ATTRIB(object);
+        while attr != R_NilValue {
+            let plain = TAG(attr) == R_NamesSymbol || (TAG(attr) == R_ClassSymbol && is_map_class(CAR(attr)));
+            if !plain {
+                return false;
+            }
+            attr = CDR(attr);
+        }
+    }
+    true
+}
+
+fn is_map_class(class: SEXP) -> bool {
+    unsafe {
+        class.rtype() == STRSXP && Rf_xlength(class) == 1
+            && CStr::from_ptr(R_CHAR(STRING_ELT(class, 0))).to_bytes() == b"tsonmap"
+    }
+}
+
+// As simplify_column, for `object` and the vectors nested in it through
+// plain lists and maps. Objects rebuilt from an rtype are left as is.
+fn simplify_nested(object: SEXP) -> RTsonResult<SEXP> {
+    if !is_plain_list(object) {
+        return Ok(object);
+    }
+    unsafe {
+        // simplifying a map would lose its names
+        if Rf_getAttrib(object, R_NamesSymbol).rtype() == NILSXP {
+            let simplified = simplify_column(object)?;
+            if simplified.rtype() != VECSXP {
+                return Ok(simplified);
+            }
+        }
+        for i in 0..Rf_xlength(object) {
+            let x = simplify_nested(VECTOR_ELT(object, i))?;
+            SET_VECTOR_ELT(object, i, x);
+        }
+    }
+    Ok(object)
+}
+
+// Drops the tsonmap class decoded maps nested in `object` are tagged with,
+// the preserve attributes mode restoring classes exactly.
+fn untag_maps(object: SEXP) {
+    if object.rtype() != VECSXP {
+        return;
+    }
+    unsafe {
+        if is_map_class(Rf_getAttrib(object, R_ClassSymbol)) {
+            Rf_setAttrib(object, R_ClassSymbol, R_NilValue);
+        }
+        for i in 0..Rf_xlength(object) {
+            untag_maps(VECTOR_ELT(object, i));
+        }
+    }
+}
+
+// JSON has no typed arrays, so a column holding NA decodes as a list of
+// length one vectors and NULLs. Turns such a list back into an atomic
+// vector, NULL elements becoming NA; any other object is returned as is.
+fn simplify_column(column: SEXP) -> RTsonResult<SEXP> {
+    if column.rtype() != VECSXP {
+        return Ok(column);
+    }
+
+    unsafe {
+        let len = Rf_xlength(column);
+        if len == 0 {
+            return Ok(column);
+        }
+        let mut target = NILSXP;
+        for i in 0..len {
+            let x = VECTOR_ELT(column, i);
+            let xtype = x.rtype();
+            if xtype == NILSXP {
+                continue;
+            }
+            if Rf_xlength(x) != 1 {
+                return Ok(column);
+            }
+            target = match (target, xtype) {
+                (NILSXP, LGLSXP) | (NILSXP, INTSXP) | (NILSXP, REALSXP) | (NILSXP, STRSXP) => xtype,
+                (INTSXP, REALSXP) | (REALSXP, INTSXP) => REALSXP,
+                (a, b) if a == b => a,
+                _ => return Ok(column),
+            };
+        }
+
+        match target {
+            INTSXP => {
+                let mut values = IntVec::alloc(len as usize);
+                for i in 0..len {
+                    let x = VECTOR_ELT(column, i);
+                    values.uset(i as usize, if x.rtype() == NILSXP { NA_INTEGER } else { *INTEGER(x) });
+                }
+                Ok(values.intor()?)
+            }
+            REALSXP => {
+                let mut values = NumVec::alloc(len as usize);
+                for i in 0..len {
+                    let x = VECTOR_ELT(column, i);
+                    values.uset(i as usize, if x.rtype() == NILSXP { na_real() } else { Rf_asReal(x) });
+                }
+                Ok(values.intor()?)
+            }
+            STRSXP => {
+                let values = CharVec::alloc(len as usize);
+                for i in 0..len {
+                    let x = VECTOR_ELT(column, i);
+                    let value = if x.rtype() == NILSXP { R_NaString } else { STRING_ELT(x, 0) };
+                    SET_STRING_ELT(values.s(), i, value);
+                }
+                Ok(values.intor()?)
+            }
+            _ => {
+                // logical, or only NULL elements
+                let values = BoolVec::alloc(len as usize);
+                let dst = LOGICAL(values.s());
+                for i in 0..len {
+                    let x = VECTOR_ELT(column, i);
+                    *dst.offset(i as isize) = if x.rtype() == NILSXP { NA_LOGICAL } else { *LOGICAL(x) };
+                }
+                Ok(values.intor()?)
+            }
+        }
+    }
+}
+
+// As simplify_column, for an entry known to be a vector of type `target`:
+// an empty list, which JSON gives no type, becomes an empty vector of that
+// type, and the scalar class a decoded single value carries is dropped.
+fn simplify_as(column: SEXP, target: SEXPTYPE) -> RTsonResult<SEXP> {
+    let column = simplify_column(column)?;
+    unsafe {
+        if column.rtype() == VECSXP && Rf_xlength(column) == 0 {
+            return Ok(Rf_allocVector(target, 0));
+        }
+        if column.rtype() == target {
+            Rf_setAttrib(column, R_ClassSymbol, R_NilValue);
+        }
+    }
+    Ok(column)
+}
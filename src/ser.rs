@@ -2,15 +2,28 @@ use super::*;
 
 pub use rustson::ser::*;
 use rustson::spec::*;
+use std::borrow::Cow;
 
-pub struct RSerializer {}
+pub struct RSerializer {
+    options: EncodeOptions,
+    losses: Losses,
+    // Position of the object being written, for error messages.
+    path: RefCell<Vec<String>>,
+}
 
 impl RSerializer {
     pub fn new( ) -> Self {
-        RSerializer{ }
+        RSerializer::with_options(EncodeOptions::default())
+    }
+
+    pub fn with_options(options: EncodeOptions) -> Self {
+        let losses = Losses::new(options.strict);
+        RSerializer{ options, losses, path: RefCell::new(Vec::new()) }
     }
 
     pub fn encoded_size(&self, value: &SEXP) -> RTsonResult<usize> {
+        self.losses.clear();
+        self.path.borrow_mut().clear();
         let mut buf = CountWriter::new();
         self.add_string(&mut buf, VERSION)?;
 
@@ -20,38 +33,86 @@ impl RSerializer {
         }
     }
 
+    // Encodes into a growable buffer in a single pass, then copies it into
+    // the raw vector at once.
     pub fn encode(&self, value: &SEXP) -> RTsonResult<RawVec> {
-        let size = self.encoded_size(value)?;
-
-        let mut buf = Vec::with_capacity(size);
-        self.add_string(&mut buf, VERSION)?;
-
-        match self.add_object(value, &mut buf) {
-            Ok(_) => {
-                let mut raw_vec = RawVec::alloc(buf.len());
+        let mut buf = Vec::new();
+        self.write(value, &mut buf)?;
 
-                unsafe {
-                    for i in 0..buf.len() {
-                        raw_vec.uset(i, buf[i]);
-                    }
-                }
-                Ok(raw_vec)
+        let raw_vec = RawVec::alloc(buf.len());
+        if !buf.is_empty() {
+            unsafe {
+                std::slice::from_raw_parts_mut(RAW(raw_vec.s()), buf.len()).copy_from_slice(&buf);
             }
-            Err(e) => Err(e),
         }
+        Ok(raw_vec)
     }
 
+    // The precision lost is not warned about here, take_warnings gives it
+    // once the caller is done.
     pub fn write(&self, value: &SEXP, writer: &mut dyn Writer) -> RTsonResult<()> {
+        self.losses.clear();
+        self.path.borrow_mut().clear();
         self.add_string(writer, VERSION)?;
-        self.add_object(value, writer)
+        self.add_object(value, writer)?;
+        Ok(())
+    }
+
+    // The messages of the precision lost by the last encoding, as an R
+    // character vector or NULL, for the caller to warn about once it holds
+    // no Rust value that needs dropping. `object` is kept protected
+    // meanwhile.
+    pub fn take_warnings(&self, object: SEXP) -> RTsonResult<SEXP> {
+        self.losses.take(object)
     }
 
     fn add_object(&self, object: &SEXP, buf: &mut dyn Writer) -> RTsonResult<()> {
-        match object.rtype() {
-            NILSXP => {
+        if self.options.preserve_attributes {
+            let attributes = extra_attributes(*object)?;
+            if !attributes.is_empty() {
+                self.add_rtype(buf, RTYPE_ATTRIBUTES, 2)?;
+                self.add_string(buf, RTYPE_DATA_KEY)?;
+                self.add_array(object, buf)?;
+                self.add_string(buf, ATTRIBUTES_KEY)?;
+                buf.add_u8(MAP_TYPE)?;
+                self.add_len(buf, attributes.len())?;
+                for (name, value) in attributes {
+                    self.add_string(buf, &name)?;
+                    self.add_element(format!("@{}", name), &value, buf)?;
+                }
+                return Ok(());
+            }
+        }
+        self.add_array(object, buf)
+    }
+
+    // Writes `object`, carrying its dim and dimnames when it is an array.
+    fn add_array(&self, object: &SEXP, buf: &mut dyn Writer) -> RTsonResult<()> {
+        if let Some(dim) = array_dim(*object)? {
+            let dimnames = unsafe { Rf_getAttrib(*object, R_DimNamesSymbol) };
+            let has_dimnames = dimnames.rtype() != NILSXP;
+
+            self.add_rtype(buf, RTYPE_ARRAY, if has_dimnames { 3 } else { 2 })?;
+            self.add_string(buf, RTYPE_DATA_KEY)?;
+            self.add_vector(object, buf)?;
+            self.add_string(buf, DIM_KEY)?;
+            self.add_i32_list(buf, &dim)?;
+            if has_dimnames {
+                self.add_string(buf, DIMNAMES_KEY)?;
+                self.add_object(&dimnames, buf)?;
+            }
+            return Ok(());
+        }
+        self.add_vector(object, buf)
+    }
+
+    // Writes `object` according to its type and class, ignoring any dim.
+    fn add_vector(&self, object: &SEXP, buf: &mut dyn Writer) -> RTsonResult<()> {
+        match vector_kind(*object, &self.options)? {
+            Kind::Null => {
                 buf.add_u8(NULL_TYPE)?;
             }
-            RAWSXP => {
+            Kind::Raw => {
                 let object_ = RawVec::rnew(*object)?;
                 buf.add_u8(LIST_UINT8_TYPE)?;
                 let len = object_.rsize() as usize;
@@ -61,204 +122,356 @@ impl RSerializer {
                     buf.put_slice(std::slice::from_raw_parts(RAW(object_.s()), len_in_bytes) )?;
                 }
             }
-            REALSXP => {
+            Kind::Time(rtype) => {
+                let tzone = get_attrib(*object, TZONE_KEY)?;
+                let units = get_attrib(*object, UNITS_KEY)?;
+                let entries = 1 + tzone.iter().count() + units.iter().count();
+
+                self.add_rtype(buf, rtype, entries)?;
+                self.add_string(buf, RTYPE_DATA_KEY)?;
+                if object.rtype() == REALSXP {
+                    let values = NumVec::new(*object)?;
+                    let values = self.real_slice(&values);
+                    check_na(&self.options, rtype, values, |x| is_na_real(*x))?;
+                    self.add_f64_list(buf, values)?;
+                } else {
+                    let values = IntVec::rnew(*object)?;
+                    let values = self.int_slice(&values);
+                    check_na(&self.options, rtype, values, |x| *x == NA_INTEGER)?;
+                    self.add_i32_list(buf, values)?;
+                }
+
+                if let Some(tzone) = tzone {
+                    self.add_string(buf, TZONE_KEY)?;
+                    self.add_object(&tzone, buf)?;
+                }
+                if let Some(units) = units {
+                    self.add_string(buf, UNITS_KEY)?;
+                    self.add_object(&units, buf)?;
+                }
+            }
+            Kind::Integer64 => {
                 let object_ = NumVec::new(*object)?;
-                if inherits(*object, "scalar")? {
-                    if object_.rsize() as usize != 1 {
-                        return http_raise(format!("real : scalar bad length : {}", object_.rsize()).to_string() );
-                    } else {
-                        buf.add_u8(DOUBLE_TYPE)?;
-                        buf.add_f64(object_.at(0).unwrap())?;
-                    }
-                } else if inherits(*object, "uint64")? {
+                let values = integer64_values(self.real_slice(&object_));
+                check_na(&self.options, "integer64", values, |x| *x == NA_INTEGER64)?;
+                buf.add_u8(LIST_INT64_TYPE)?;
+                self.add_len(buf, values.len())?;
+                for x in values {
+                    buf.add_i64(x)?;
+                }
+            }
+            Kind::Real(clazz) => {
+                let object_ = NumVec::new(*object)?;
+                let values = self.real_slice(&object_);
+                check_na(&self.options, "real", values, |x| is_na_real(*x))?;
+                if clazz == Some("scalar") {
+                    check_scalar("real", values.len())?;
+                    buf.add_u8(DOUBLE_TYPE)?;
+                    buf.add_f64(values[0])?;
+                } else if clazz == Some("uint64") {
+                    self.losses.check("real", "uint64", values.iter()
+                        .map(|x| real_cast_lossy(*x, -1.0, std::u64::MAX as f64)))?;
                     buf.add_u8(LIST_UINT64_TYPE)?;
-                    self.add_len(buf, object_.rsize() as usize)?;
-                    for x in object_ {
-                        buf.add_u64(x as u64)?;
+                    self.add_len(buf, values.len())?;
+                    for x in convert_reals(values, std::u64::MAX, |x| x as u64) {
+                        buf.add_u64(x)?;
                     }
-                } else if inherits(*object, "int64")? {
+                } else if clazz == Some("int64") {
+                    self.losses.check("real", "int64", values.iter()
+                        .map(|x| real_cast_lossy(*x, std::i64::MIN as f64, std::i64::MAX as f64)))?;
                     buf.add_u8(LIST_INT64_TYPE)?;
-                    self.add_len(buf, object_.rsize() as usize)?;
-                    for x in object_ {
-                        buf.add_i64(x as i64)?;
+                    self.add_len(buf, values.len())?;
+                    for x in convert_reals(values, std::i64::MIN, |x| x as i64) {
+                        buf.add_i64(x)?;
                     }
+                } else if clazz == Some("uint32") {
+                    self.losses.check("real", "uint32", values.iter()
+                        .map(|x| real_cast_lossy(*x, -1.0, std::u32::MAX as f64)))?;
+                    buf.add_u8(LIST_UINT32_TYPE)?;
+                    self.add_len(buf, values.len())?;
+                    for x in convert_reals(values, std::u32::MAX, |x| x as u32) {
+                        buf.add_u32(x)?;
+                    }
+                } else if clazz == Some("float32") {
+                    buf.add_u8(LIST_FLOAT32_TYPE)?;
+                    self.add_len(buf, values.len())?;
+                    for x in values {
+                        buf.put_slice(&real_to_f32(*x).to_le_bytes())?;
+                    }
+                } else {
+                    self.add_f64_list(buf, values)?;
+                }
+            }
+            Kind::Factor => {
+                if self.options.expand_factors {
+                    factor_labels(*object, |labels| Ok(self.add_object(&labels, buf)?))?;
                 } else {
-                    buf.add_u8(LIST_FLOAT64_TYPE)?;
-                    let len = object_.rsize() as usize;
-                    self.add_len(buf, len)?;
-                    let len_in_bytes = len * 8;
+                    let codes = IntVec::rnew(*object)?;
+                    let codes = self.int_slice(&codes);
+                    check_na(&self.options, "factor", codes, |x| *x == NA_INTEGER)?;
 
-                    unsafe {
-                        buf.put_slice(std::slice::from_raw_parts(REAL(object_.s()) as *const u8, len_in_bytes) )?;
-                    }
+                    self.add_rtype(buf, RTYPE_FACTOR, 3)?;
+                    self.add_string(buf, RTYPE_DATA_KEY)?;
+                    self.add_i32_list(buf, codes)?;
+
+                    self.add_string(buf, FACTOR_LEVELS_KEY)?;
+                    let levels = unsafe { Rf_getAttrib(*object, R_LevelsSymbol) };
+                    self.add_object(&levels, buf)?;
+
+                    self.add_string(buf, FACTOR_ORDERED_KEY)?;
+                    buf.add_u8(BOOL_TYPE)?;
+                    buf.add_u8(if inherits(*object, "ordered")? { 1 } else { 0 })?;
                 }
             }
-            INTSXP => {
+            Kind::Int(clazz) => {
                 let object_ = IntVec::rnew(*object)?;
-                if inherits(*object, "scalar")? {
-                    if object_.rsize() as usize != 1 {
-                        return http_raise(format!("int : scalar bad length : {}", object_.rsize()).to_string());
-                    } else {
-                        buf.add_u8(INTEGER_TYPE)?;
-                        buf.add_i32(object_.at(0).unwrap())?;
-                    }
+                let values = self.int_slice(&object_);
+                check_na(&self.options, "int", values, |x| *x == NA_INTEGER)?;
+                if clazz == Some("scalar") {
+                    check_scalar("int", values.len())?;
+                    buf.add_u8(INTEGER_TYPE)?;
+                    buf.add_i32(values[0])?;
                 } else {
-                    if inherits(*object, "int8")? {
+                    if clazz == Some("int8") {
+                        let values: Vec<i8> = convert_ints(values, "int8", sentinel(&self.options, std::i8::MIN))?;
                         buf.add_u8(LIST_INT8_TYPE)?;
-                        let len = object_.rsize() as usize;
-                        self.add_len(buf, len)?;
-                        let len_in_bytes = len;
-
-                        unsafe {
-                            buf.put_slice(std::slice::from_raw_parts(INTEGER(object_.s()) as *const u8, len_in_bytes) )?;
+                        self.add_len(buf, values.len())?;
+                        for x in values {
+                            buf.put_slice(&x.to_le_bytes())?;
                         }
-                    } else if inherits(*object, "int16")? {
+                    } else if clazz == Some("int16") {
+                        let values: Vec<i16> = convert_ints(values, "int16", sentinel(&self.options, std::i16::MIN))?;
                         buf.add_u8(LIST_INT16_TYPE)?;
-                        let len = object_.rsize() as usize;
-                        self.add_len(buf, len)?;
-                        let len_in_bytes = len * 2;
-                        unsafe {
-                            buf.put_slice(std::slice::from_raw_parts(INTEGER(object_.s()) as *const u8, len_in_bytes) )?;
+                        self.add_len(buf, values.len())?;
+                        for x in values {
+                            buf.put_slice(&x.to_le_bytes())?;
                         }
-                    } else if inherits(*object, "int64")? {
+                    } else if clazz == Some("int64") {
+                        let values: Vec<i64> = convert_ints(values, "int64", sentinel(&self.options, std::i64::MIN))?;
                         buf.add_u8(LIST_INT64_TYPE)?;
-                        let len = object_.rsize() as usize;
-                        self.add_len(buf, len)?;
-                        let len_in_bytes = len * 8;
-                        unsafe {
-                            buf.put_slice(std::slice::from_raw_parts(INTEGER(object_.s()) as *const u8, len_in_bytes) )?;
+                        self.add_len(buf, values.len())?;
+                        for x in values {
+                            buf.add_i64(x)?;
                         }
-                    } else if inherits(*object, "uint8")? {
+                    } else if clazz == Some("uint8") {
+                        let values: Vec<u8> = convert_ints(values, "uint8", None)?;
                         buf.add_u8(LIST_UINT8_TYPE)?;
-                        let len = object_.rsize() as usize;
-                        self.add_len(buf, len)?;
-                        let len_in_bytes = len  ;
-                        unsafe {
-                            buf.put_slice(std::slice::from_raw_parts(INTEGER(object_.s()) as *const u8, len_in_bytes) )?;
-                        }
-                    } else if inherits(*object, "uint16")? {
+                        self.add_len(buf, values.len())?;
+                        buf.put_slice(&values)?;
+                    } else if clazz == Some("uint16") {
+                        let values: Vec<u16> = convert_ints(values, "uint16", sentinel(&self.options, std::u16::MAX))?;
                         buf.add_u8(LIST_UINT16_TYPE)?;
-                        let len = object_.rsize() as usize;
-                        self.add_len(buf, len)?;
-                        let len_in_bytes = len * 2;
-                        unsafe {
-                            buf.put_slice(std::slice::from_raw_parts(INTEGER(object_.s()) as *const u8, len_in_bytes) )?;
+                        self.add_len(buf, values.len())?;
+                        for x in values {
+                            buf.put_slice(&x.to_le_bytes())?;
                         }
-                    } else if inherits(*object, "uint64")? {
+                    } else if clazz == Some("uint64") {
+                        let values: Vec<u64> = convert_ints(values, "uint64", sentinel(&self.options, std::u64::MAX))?;
                         buf.add_u8(LIST_UINT64_TYPE)?;
-                        let len = object_.rsize() as usize;
-                        self.add_len(buf, len)?;
-                        let len_in_bytes = len * 8;
-                        unsafe {
-                            buf.put_slice(std::slice::from_raw_parts(INTEGER(object_.s()) as *const u8, len_in_bytes) )?;
+                        self.add_len(buf, values.len())?;
+                        for x in values {
+                            buf.add_u64(x)?;
                         }
-                    } else if inherits(*object, "uint32")? {
+                    } else if clazz == Some("uint32") {
+                        let values: Vec<u32> = convert_ints(values, "uint32", sentinel(&self.options, std::u32::MAX))?;
                         buf.add_u8(LIST_UINT32_TYPE)?;
-                        let len = object_.rsize() as usize;
-                        self.add_len(buf, len)?;
-                        let len_in_bytes = len * 4;
-                        unsafe {
-                            buf.put_slice(std::slice::from_raw_parts(INTEGER(object_.s()) as *const u8, len_in_bytes) )?;
+                        self.add_len(buf, values.len())?;
+                        for x in values {
+                            buf.add_u32(x)?;
                         }
                     } else {
-                        buf.add_u8(LIST_INT32_TYPE)?;
-                        let len = object_.rsize() as usize;
-                        self.add_len(buf, len)?;
-                        let len_in_bytes = len * 4;
-                        unsafe {
-                            buf.put_slice(std::slice::from_raw_parts(INTEGER(object_.s()) as *const u8, len_in_bytes) )?;
-                        }
+                        self.add_i32_list(buf, values)?;
                     }
                 }
             }
-            LGLSXP => {
-                let object_ = BoolVec::rnew(*object)?;
-                if object_.rsize() as usize != 1 {
-                    return http_raise(format!("bool : bad length : {}", object_.rsize()).to_string());
-                } else {
+            Kind::Logical => {
+                let values = logical_slice(object);
+                check_na(&self.options, "bool", values, |x| *x == NA_LOGICAL)?;
+                if values.len() == 1 && values[0] != NA_LOGICAL {
                     buf.add_u8(BOOL_TYPE)?;
-                    let v = object_.at(0).unwrap();
-                    if v {
+                    if values[0] != 0 {
                         buf.add_u8(1)?;
                     } else {
                         buf.add_u8(0)?;
                     }
+                } else {
+                    self.add_rtype(buf, RTYPE_LOGICAL, 1)?;
+                    self.add_string(buf, RTYPE_DATA_KEY)?;
+                    buf.add_u8(LIST_UINT8_TYPE)?;
+                    self.add_len(buf, values.len())?;
+                    for x in values {
+                        buf.add_u8(logical_to_u8(*x))?;
+                    }
+                }
+            }
+            Kind::Complex => {
+                let values = complex_slice(object);
+                if self.options.na == NaPolicy::Error {
+                    if let Some(i) = first_na_complex(values) {
+                        return Err(na_error("complex", i));
+                    }
                 }
+                self.add_rtype(buf, RTYPE_COMPLEX, 1)?;
+                self.add_string(buf, RTYPE_DATA_KEY)?;
+                self.add_f64_list(buf, values)?;
             }
-            STRSXP => {
+            Kind::Str(scalar) => {
                 let object_ = CharVec::rnew(*object)?;
                 let len = object_.rsize() as usize;
-                if inherits(*object, "scalar")? {
-                    if len != 1 {
-                        return http_raise(format!("str : scalar bad length : {}", object_.rsize()).to_string());
+                let nas = string_nas(*object);
+                if self.options.na == NaPolicy::Error && !nas.is_empty() {
+                    return Err(na_error("str", nas[0]));
+                }
+                if scalar {
+                    check_scalar("str", len)?;
+                }
 
-                    } else {
-                        self.add_string(buf, &object_.at(0).map_err(|e| RError::other(e))?)?;
+                if !nas.is_empty() {
+                    self.add_rtype(buf, RTYPE_CHARACTER, 2)?;
+                    self.add_string(buf, RTYPE_DATA_KEY)?;
+                    self.add_str_list(buf, object)?;
+                    self.add_string(buf, RTYPE_NA_KEY)?;
+                    buf.add_u8(LIST_INT32_TYPE)?;
+                    self.add_len(buf, nas.len())?;
+                    for i in nas {
+                        buf.add_i32(i as i32)?;
                     }
+                } else if scalar {
+                    self.add_string(buf, &string_elt(*object, 0)?)?;
                 } else {
-                    buf.add_u8(LIST_STRING_TYPE)?;
-                    let mut len_in_bytes = 0;
-
-                    for i in 0..len {
-                        let value = object_.at(i).map_err(|e| RError::other(e))?;
-                        len_in_bytes += value.as_bytes().len() + 1;
-                    }
-
-                    self.add_len(buf, len_in_bytes)?;
-
-                    for i in 0..len {
-                        let value = object_.at(i).map_err(|e| RError::other(e))?;
-                        self.add_cstring(buf, &value)?;
-                    }
-
-                    // let mut len_in_bytes = 0;
-                    //
-                    // for value in object_ {
-                    //     len_in_bytes += value.as_bytes().len() + 1;
-                    // }
-                    //
-                    // self.add_len(buf, len_in_bytes)?;
-                    //
-                    // let object_ = CharVec::rnew(*object)?;
-                    // for value in object_ {
-                    //     self.add_cstring2(buf, &value)?;
-                    // }
+                    self.add_str_list(buf, object)?;
                 }
             }
-            VECSXP => {
-                /* generic vectors */
-                // empty list
+            Kind::DataFrame => {
                 let rlist = RList::new(*object)?;
-
                 let names: CharVec = RName::name(&rlist);
+                let (nrow, row_names) = data_frame_rows(*object);
+                self.add_rtype(buf, RTYPE_DATA_FRAME, if row_names.is_some() { 3 } else { 2 })?;
+                self.add_string(buf, DF_NROW_KEY)?;
+                buf.add_u8(INTEGER_TYPE)?;
+                buf.add_i32(nrow as i32)?;
 
-                if names.rsize() > 0 || inherits(*object, "tsonmap")? {
-                    buf.add_u8(MAP_TYPE)?;
-                    self.add_len(buf, names.rsize() as usize)?;
-
-                    let mut index = 0;
+                self.add_string(buf, DF_COLUMNS_KEY)?;
+                buf.add_u8(MAP_TYPE)?;
+                let keys = map_keys(&names, rlist.rsize() as usize, column_names_policy(self.options.names))?;
+                self.add_len(buf, keys.len())?;
+                for (index, key) in keys {
+                    self.add_string(buf, &key)?;
+                    self.add_element(format!("${}", key), &list_elt(*object, index), buf)?;
+                }
 
-                    for x in rlist {
-                        self.add_string(buf, &names.at(index)?)?;
-                        self.add_object(&x, buf)?;
-                        index = index + 1;
-                    }
-                } else {
-                    buf.add_u8(LIST_TYPE)?;
-                    self.add_len(buf, rlist.rsize() as usize)?;
-                    for x in rlist {
-                        self.add_object(&x, buf)?;
-                    }
+                if let Some(row_names) = row_names {
+                    self.add_string(buf, DF_ROW_NAMES_KEY)?;
+                    self.add_object(&row_names, buf)?;
+                }
+            }
+            Kind::Map => {
+                let rlist = RList::new(*object)?;
+                let names: CharVec = RName::name(&rlist);
+                let keys = map_keys(&names, rlist.rsize() as usize, self.options.names)?;
+                buf.add_u8(MAP_TYPE)?;
+                self.add_len(buf, keys.len())?;
+                for (index, key) in keys {
+                    self.add_string(buf, &key)?;
+                    self.add_element(format!("${}", key), &list_elt(*object, index), buf)?;
+                }
+            }
+            Kind::List => {
+                let rlist = RList::new(*object)?;
+                buf.add_u8(LIST_TYPE)?;
+                self.add_len(buf, rlist.rsize() as usize)?;
+                for (index, x) in rlist.into_iter().enumerate() {
+                    self.add_element(format!("[[{}]]", index + 1), &x, buf)?;
                 }
             }
-            _ => {
-                return http_raise(format!("bad object type : {}", object.rtype()).to_string())
+            Kind::Language => {
+                self.add_rtype(buf, RTYPE_LANGUAGE, 1)?;
+                self.add_string(buf, RTYPE_DATA_KEY)?;
+                self.add_string(buf, &deparse_language(*object)?)?;
             }
+            Kind::Environment => {
+                self.add_rtype(buf, RTYPE_ENVIRONMENT, 1)?;
+                self.add_string(buf, RTYPE_DATA_KEY)?;
+                self.add_string(buf, environment_name(*object)?)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Writes `object` as the element `segment` of the current path.
+    fn add_element(&self, segment: String, object: &SEXP, buf: &mut dyn Writer) -> RTsonResult<()> {
+        self.path.borrow_mut().push(segment);
+        self.add_object(object, buf)?;
+        self.path.borrow_mut().pop();
+        Ok(())
+    }
+
+    // Writes a string list, NA elements are written as empty strings.
+    fn add_str_list(&self, buf: &mut dyn Writer, object: &SEXP) -> RTsonResult<()> {
+        let values = strings(*object)?;
+        let values = values.iter()
+            .map(|x| self.nul_free(x))
+            .collect::<RTsonResult<Vec<_>>>()?;
+        buf.add_u8(LIST_STRING_TYPE)?;
+        let len_in_bytes = values.iter().map(|x| x.len() + 1).sum();
+        self.add_len(buf, len_in_bytes)?;
+        for value in values {
+            buf.put_slice(value.as_bytes())?;
+            buf.add_u8(0)?;
+        }
+        Ok(())
+    }
+
+    fn add_f64_list(&self, buf: &mut dyn Writer, values: &[f64]) -> RTsonResult<()> {
+        buf.add_u8(LIST_FLOAT64_TYPE)?;
+        self.add_len(buf, values.len())?;
+        let len_in_bytes = values.len() * 8;
+        unsafe {
+            buf.put_slice(std::slice::from_raw_parts(values.as_ptr() as *const u8, len_in_bytes))?;
         }
+        Ok(())
+    }
 
+    fn add_i32_list(&self, buf: &mut dyn Writer, values: &[i32]) -> RTsonResult<()> {
+        buf.add_u8(LIST_INT32_TYPE)?;
+        self.add_len(buf, values.len())?;
+        let len_in_bytes = values.len() * 4;
+        unsafe {
+            buf.put_slice(std::slice::from_raw_parts(values.as_ptr() as *const u8, len_in_bytes))?;
+        }
         Ok(())
     }
 
+    fn real_slice<'a>(&self, object: &'a NumVec) -> &'a [f64] {
+        let len = object.rsize() as usize;
+        if len == 0 {
+            return &[];
+        }
+        unsafe {
+            std::slice::from_raw_parts(REAL(object.s()), len)
+        }
+    }
+
+    fn int_slice<'a>(&self, object: &'a IntVec) -> &'a [i32] {
+        let len = object.rsize() as usize;
+        if len == 0 {
+            return &[];
+        }
+        unsafe {
+            std::slice::from_raw_parts(INTEGER(object.s()), len)
+        }
+    }
+
+    // Starts a reserved map tagged with `rtype`; the caller writes the
+    // `entries` remaining key/value pairs.
+    fn add_rtype(&self, buf: &mut dyn Writer, rtype: &str, entries: usize) -> RTsonResult<()> {
+        buf.add_u8(MAP_TYPE)?;
+        self.add_len(buf, entries + 1)?;
+        self.add_string(buf, RTYPE_KEY)?;
+        self.add_string(buf, rtype)
+    }
+
     fn add_len(&self, buf: &mut dyn Writer, len: usize) -> RTsonResult<()> {
         if len > MAX_LIST_LENGTH {
             return http_raise("list too large");
@@ -273,11 +486,25 @@ impl RSerializer {
     }
 
     fn add_cstring(&self, buf: &mut dyn Writer, value: &str) -> RTsonResult<()> {
-        buf.put_slice(value.as_bytes())?;
+        buf.put_slice(self.nul_free(value)?.as_bytes())?;
         buf.add_u8(0)?;
         Ok(())
     }
 
+    // `value` with any embedded NUL replaced by `\u0000`, which is not
+    // unescaped on decoding, or an error naming where the string is when
+    // escaping is off.
+    fn nul_free<'a>(&self, value: &'a str) -> RTsonResult<Cow<'a, str>> {
+        if !value.contains('\0') {
+            Ok(Cow::Borrowed(value))
+        } else if self.options.escape_nul {
+            Ok(Cow::Owned(value.replace('\0', "\\u0000")))
+        } else {
+            let path = self.path.borrow().concat();
+            http_raise(format!("str : embedded nul at {}", if path.is_empty() { "top level" } else { path.as_str() }))
+        }
+    }
+
     // fn add_cstring2(&self, buf: &mut dyn Writer, value: &CString) -> RTsonResult<()> {
     //     buf.put_slice(value.as_bytes())?;
     //     buf.add_u8(0)?;
@@ -286,3 +513,125 @@ impl RSerializer {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutil::*;
+
+    fn serializer_bytes(object: SEXP) -> RTsonResult<Vec<u8>> {
+        let mut bytes = Vec::new();
+        RSerializer::new().write(&object, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    // Each class is written as the list a hand-built Value encodes to, which
+    // decodes back to `decoded`.
+    #[test]
+    fn classed_ints() {
+        let _r = session();
+        let cases = vec![
+            ("int8", "c(1L, 2L, 3L)", Value::LSTI8(vec![1, 2, 3]), "structure(1:3, class = 'int8')"),
+            ("int8", "integer(0)", Value::LSTI8(vec![]), "structure(integer(0), class = 'int8')"),
+            ("int8", "c(0L, 127L, NA)", Value::LSTI8(vec![0, 127, std::i8::MIN]),
+             "structure(c(0L, 127L, NA), class = 'int8')"),
+            ("int16", "c(-32767L, NA)", Value::LSTI16(vec![-32767, std::i16::MIN]),
+             "structure(c(-32767L, NA), class = 'int16')"),
+            ("uint8", "c(0L, 255L)", Value::LSTU8(vec![0, 255]), "as.raw(c(0, 255))"),
+            ("uint16", "c(65534L, NA)", Value::LSTU16(vec![65534, std::u16::MAX]),
+             "structure(c(65534L, NA), class = 'uint16')"),
+            ("uint32", "c(7L, NA)", Value::LSTU32(vec![7, std::u32::MAX]), "structure(c(7, NA), class = 'uint32')"),
+            ("int64", "c(-5L, NA)", Value::LSTI64(vec![-5, std::i64::MIN]), "structure(c(-5, NA), class = 'int64')"),
+            ("uint64", "c(5L, NA)", Value::LSTU64(vec![5, std::u64::MAX]), "structure(c(5, NA), class = 'uint64')"),
+        ];
+        for (clazz, values, expected, decoded) in cases {
+            let object = r(&format!("structure({}, class = '{}')", values, clazz));
+            let expected = encode(&expected).unwrap();
+            assert_eq!(serializer_bytes(object).unwrap(), expected, "{} {}", clazz, values);
+            let x = preserve(RTsonDeserializer::new().read_slice(&expected).unwrap());
+            assert!(identical(x, r(decoded)), "{} {}", clazz, values);
+        }
+    }
+
+    #[test]
+    fn classed_ints_out_of_range() {
+        let _r = session();
+        let cases = [
+            ("int8", "c(1L, 128L)", "int8 : value 128 out of range at index 1"),
+            ("int8", "c(-128L)",
+             "int8 : value -128 at index 0 is reserved as the NA sentinel, it is only written when NA is an error"),
+            ("int16", "c(0L, 0L, -40000L)", "int16 : value -40000 out of range at index 2"),
+            ("int16", "c(1L, -32768L)",
+             "int16 : value -32768 at index 1 is reserved as the NA sentinel, it is only written when NA is an error"),
+            ("uint8", "c(256L)", "uint8 : value 256 out of range at index 0"),
+            ("uint8", "c(1L, NA)", "uint8 : NA not representable at index 1"),
+            ("uint16", "c(-1L)", "uint16 : value -1 out of range at index 0"),
+            ("uint16", "c(65535L)",
+             "uint16 : value 65535 at index 0 is reserved as the NA sentinel, it is only written when NA is an error"),
+            ("uint64", "c(3L, -3L)", "uint64 : value -3 out of range at index 1"),
+            ("uint32", "c(-1L)", "uint32 : value -1 out of range at index 0"),
+        ];
+        for &(clazz, values, message) in cases.iter() {
+            let object = r(&format!("structure({}, class = '{}')", values, clazz));
+            assert_eq!(serializer_bytes(object).unwrap_err().to_string(), message);
+            assert!(r_to_value(object).is_err(), "{} {}", clazz, values);
+        }
+
+        // nothing is reserved when NA is an error
+        let options = EncodeOptions { na: NaPolicy::Error, ..EncodeOptions::default() };
+        let mut bytes = Vec::new();
+        RSerializer::with_options(options).write(&r("structure(c(-128L, 1L), class = 'int8')"), &mut bytes).unwrap();
+        assert_eq!(bytes, encode(&Value::LSTI8(vec![-128, 1])).unwrap());
+    }
+
+    #[test]
+    fn float32() {
+        let _r = session();
+        let expected = encode(&Value::LSTF32(vec![1.5, -0.25, std::f32::INFINITY])).unwrap();
+        let x = r("structure(c(1.5, -0.25, Inf), class = 'float32')");
+        assert_eq!(serializer_bytes(x).unwrap(), expected);
+        let options = EncodeOptions { float32: true, ..EncodeOptions::default() };
+        let mut bytes = Vec::new();
+        RSerializer::with_options(options).write(&r("c(1.5, -0.25, Inf)"), &mut bytes).unwrap();
+        assert_eq!(bytes, expected);
+
+        let deser = RTsonDeserializer::new();
+        for &code in &["structure(c(1.5, -0.25, Inf), class = 'float32')",
+                       "structure(c(NA, 1), class = 'float32')",
+                       "structure(numeric(0), class = 'float32')"] {
+            let x = r(code);
+            let y = preserve(deser.read_slice(&serializer_bytes(x).unwrap()).unwrap());
+            assert!(identical(x, y), "{} does not round trip through TSON", code);
+        }
+    }
+
+    // The single pass writes the bytes the writer gets, of the size the
+    // counting pass gives.
+    #[test]
+    fn encode_output() {
+        let _r = session();
+        let ser = RSerializer::new();
+        for &code in &["1:10", "list(a = 'x', b = list(1.5, NULL))", "character(0)",
+                       "data.frame(x = 1:2, y = c('a', NA), stringsAsFactors = FALSE)",
+                       "as.double(1:1e5)"] {
+            let x = r(code);
+            let expected = serializer_bytes(x).unwrap();
+            assert_eq!(raw_bytes(&ser.encode(&x).unwrap()), &expected[..], "{}", code);
+            assert_eq!(ser.encoded_size(&x).unwrap(), expected.len(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn embedded_nul() {
+        let ser = RSerializer::new();
+        assert_eq!(ser.nul_free("a\0b").unwrap_err().to_string(), "str : embedded nul at top level");
+        ser.path.borrow_mut().extend(vec!["$x".to_string(), "[[2]]".to_string(), "@names".to_string()]);
+        assert_eq!(ser.nul_free("a\0b").unwrap_err().to_string(), "str : embedded nul at $x[[2]]@names");
+        assert_eq!(ser.nul_free("ab").unwrap(), "ab");
+
+        let mut options = EncodeOptions::default();
+        options.escape_nul = true;
+        let ser = RSerializer::with_options(options);
+        assert_eq!(ser.nul_free("a\0b").unwrap(), "a\\u0000b");
+    }
+}
@@ -0,0 +1,74 @@
+use super::*;
+use std::os::raw::{c_char, c_int};
+use std::sync::{Mutex, MutexGuard, Once};
+
+// Tests run against an R embedded in the test binary. R is started once
+// and is single threaded, so a test holds the session for as long as it
+// touches R objects.
+
+extern "C" {
+    fn Rf_initEmbeddedR(argc: c_int, argv: *mut *mut c_char) -> c_int;
+    fn R_PreserveObject(object: SEXP);
+    fn Rf_defineVar(symbol: SEXP, value: SEXP, env: SEXP);
+    static mut R_CStackLimit: usize;
+}
+
+static START: Once = Once::new();
+static SESSION: Mutex<()> = Mutex::new(());
+
+pub fn session() -> MutexGuard<'static, ()> {
+    let guard = SESSION.lock().unwrap_or_else(|e| e.into_inner());
+    START.call_once(|| {
+        let args: Vec<CString> = ["R", "--vanilla", "--silent", "--no-save"].iter()
+            .map(|x| CString::new(*x).unwrap())
+            .collect();
+        let mut argv: Vec<*mut c_char> = args.iter().map(|x| x.as_ptr() as *mut c_char).collect();
+        unsafe {
+            Rf_initEmbeddedR(argv.len() as c_int, argv.as_mut_ptr());
+            // tests run on other threads than the one R was started on
+            R_CStackLimit = std::usize::MAX;
+        }
+    });
+    guard
+}
+
+// Evaluates `code` in the global environment. The result is preserved for
+// the rest of the session.
+pub fn r(code: &str) -> SEXP {
+    unsafe {
+        let text = Rf_protect(Rf_mkString(c_str(code).unwrap().as_ptr()));
+        let mut status = 0;
+        let exprs = Rf_protect(R_ParseVector(text, -1, &mut status, R_NilValue));
+        assert_eq!(status, PARSE_OK, "cannot parse {}", code);
+        let mut value = R_NilValue;
+        for i in 0..Rf_xlength(exprs) {
+            let mut error = 0;
+            value = R_tryEval(VECTOR_ELT(exprs, i), R_GlobalEnv, &mut error);
+            assert_eq!(error, 0, "cannot evaluate {}", code);
+        }
+        R_PreserveObject(value);
+        Rf_unprotect(2);
+        value
+    }
+}
+
+// Keeps `value` from being collected for the rest of the session.
+pub fn preserve(value: SEXP) -> SEXP {
+    unsafe {
+        R_PreserveObject(value);
+    }
+    value
+}
+
+// Binds `value` to `name` in the global environment.
+pub fn assign(name: &str, value: SEXP) {
+    unsafe {
+        Rf_defineVar(symbol(name).unwrap(), value, R_GlobalEnv);
+    }
+}
+
+pub fn identical(x: SEXP, y: SEXP) -> bool {
+    assign(".x", x);
+    assign(".y", y);
+    unsafe { Rf_asLogical(r("identical(.x, .y)")) == 1 }
+}